@@ -2,8 +2,8 @@ use crossbeam::{scope, unbounded};
 use minifb::{Key, Window, WindowOptions};
 use raybird::{
     scene_loader,
-    sensor::{Sensor, SensorDimensions},
-    tracer::{Screen, StratisfiedImageSampler, Vector3},
+    sensor::{Sample, Sensor, SensorDimensions},
+    tracer::{Screen, StratisfiedImageSampler},
 };
 
 const WIDTH: usize = 1920;
@@ -31,15 +31,15 @@ fn main() {
             width: WIDTH,
             height: HEIGHT,
         };
-        let (sender, receiver) = unbounded::<[(usize, Vector3<f64>); NUM_SAMPLES]>();
+        let (sender, receiver) = unbounded::<Vec<(usize, Sample)>>();
         for a in 0..NUM_THREADS {
             let se = sender.clone();
             let scene_ref = &scene;
             scoped.spawn(move |_| {
                 let mut i = a * (HEIGHT / NUM_THREADS) * WIDTH;
                 loop {
-                    let mut samples = [(0, Vector3::new(0.0, 0.0, 0.0)); NUM_SAMPLES];
-                    for sample in samples.iter_mut() {
+                    let mut samples = Vec::with_capacity(NUM_SAMPLES);
+                    for _ in 0..NUM_SAMPLES {
                         let pixel = dimensions.pixel_for_index(i);
                         let ray = scene_ref.camera.ray(
                             pixel.x,
@@ -48,12 +48,12 @@ fn main() {
                             dimensions.height,
                         );
                         let index = dimensions.index_for_pixel(pixel);
-                        *sample = (
+                        samples.push((
                             index,
                             StratisfiedImageSampler::new(scene_ref, ray, 1, 6)
                                 .next()
                                 .unwrap(),
-                        );
+                        ));
                         i += 1;
                     }
 
@@ -64,13 +64,14 @@ fn main() {
             });
         }
 
-        let mut sensor = Sensor::new(dimensions, 1.0 / GAMMA);
+        let mut sensor = Sensor::new(dimensions, 1.0 / GAMMA)
+            .with_light_groups(scene.light_groups().len());
         while window.is_open() && !window.is_key_down(Key::Escape) {
             while let Ok(samples) = receiver.try_recv() {
-                for (i, sample) in samples.iter() {
-                    sensor.add_sample(*i, *sample);
-                    let color = sensor.color_at(*i);
-                    screen.write(*i, color.x, color.y, color.z);
+                for (i, sample) in samples {
+                    sensor.add_sample(i, sample);
+                    let color = sensor.color_at(i);
+                    screen.write(i, color.x, color.y, color.z);
                 }
             }
 
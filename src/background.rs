@@ -0,0 +1,91 @@
+use crate::environment::Environment;
+use crate::image::Image;
+use nalgebra::Vector3;
+use std::f64;
+use std::sync::Arc;
+
+// rows of the map a background is baked into for lighting
+const ROWS: usize = 64;
+
+// A plain backdrop beyond a scene's objects, in nits. A gradient runs up
+// from the nadir through the horizon to the zenith, in step with the height
+// of the direction looked along.
+#[derive(Clone)]
+pub enum Background {
+    Black,
+    Solid(Vector3<f64>),
+    Gradient {
+        bottom: Vector3<f64>,
+        horizon: Vector3<f64>,
+        top: Vector3<f64>,
+    },
+}
+
+impl Background {
+    pub fn gradient(bottom: Vector3<f64>, top: Vector3<f64>) -> Self {
+        Self::three_colour_gradient(bottom, (bottom + top) / 2.0, top)
+    }
+
+    pub fn three_colour_gradient(bottom: Vector3<f64>, horizon: Vector3<f64>, top: Vector3<f64>) -> Self {
+        Background::Gradient { bottom, horizon, top }
+    }
+
+    pub fn radiance(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        match *self {
+            Background::Black => Vector3::zeros(),
+            Background::Solid(colour) => colour,
+            Background::Gradient { bottom, horizon, top } => {
+                let height = direction.y.clamp(-1.0, 1.0);
+                if height < 0.0 {
+                    horizon.lerp(&bottom, -height)
+                } else {
+                    horizon.lerp(&top, height)
+                }
+            }
+        }
+    }
+
+    // the background as an environment to light a scene with, which a black
+    // one never does
+    pub fn environment(&self) -> Option<Environment> {
+        if let Background::Black = self {
+            return None;
+        }
+        // a gradient only changes with height, so one column will do
+        let pixels = (0..ROWS)
+            .map(|y| {
+                let theta = f64::consts::PI * (y as f64 + 0.5) / ROWS as f64;
+                self.radiance(&Vector3::new(theta.sin(), theta.cos(), 0.0))
+            })
+            .collect();
+        Some(Environment::new(Arc::new(Image::new(1, ROWS, pixels)), 0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gradients_pass_through_the_horizon_colour() {
+        let (bottom, horizon, top) = (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        let background = Background::three_colour_gradient(bottom, horizon, top);
+        assert_eq!(background.radiance(&Vector3::new(0.0, -1.0, 0.0)), bottom);
+        assert_eq!(background.radiance(&Vector3::new(1.0, 0.0, 0.0)), horizon);
+        assert_eq!(background.radiance(&Vector3::new(0.0, 1.0, 0.0)), top);
+        let halfway = background.radiance(&Vector3::new(0.0, 0.5, 0.0));
+        assert_eq!(halfway, Vector3::new(0.0, 0.5, 0.5));
+
+        let two = Background::gradient(bottom, top);
+        assert_eq!(two.radiance(&Vector3::new(0.0, 0.0, 1.0)), Vector3::new(0.5, 0.0, 0.5));
+    }
+
+    #[test]
+    fn solid_backgrounds_light_like_a_uniform_environment() {
+        assert!(Background::Black.environment().is_none());
+        let environment = Background::Solid(Vector3::repeat(2.0)).environment().unwrap();
+        // a quarter of 2 nits integrated over the sphere
+        assert!((environment.average_irradiance() - 2.0 * f64::consts::PI).abs() < 1e-2);
+        assert_eq!(environment.radiance(&Vector3::new(0.3, -0.8, 0.2).normalize()), Vector3::repeat(2.0));
+    }
+}
@@ -0,0 +1,514 @@
+use crate::fresnel::{self, Conductor};
+use crate::ray::DirectionExt;
+use nalgebra::Vector3;
+use std::f64;
+use std::sync::Arc;
+
+// Scattering functions that can be evaluated as well as sampled. Directions
+// are in a shading frame where the normal is +z, both point away from the
+// surface, and `eval` includes the cosine of the incoming direction so that
+// `eval / pdf` is the weight of a sample.
+pub trait Bxdf: Send + Sync {
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64>;
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64;
+    fn sample(&self, wo: &Vector3<f64>, u: f64, v: f64) -> Option<Vector3<f64>>;
+}
+
+fn same_hemisphere(wo: &Vector3<f64>, wi: &Vector3<f64>) -> bool {
+    wo.z > 0.0 && wi.z > 0.0
+}
+
+fn reflect(wo: &Vector3<f64>, m: &Vector3<f64>) -> Vector3<f64> {
+    m * (2.0 * wo.dot(m)) - wo
+}
+
+pub struct Lambertian {
+    reflectance: Vector3<f64>,
+}
+
+impl Lambertian {
+    pub fn new(reflectance: Vector3<f64>) -> Self {
+        Self { reflectance }
+    }
+}
+
+impl Bxdf for Lambertian {
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        if !same_hemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+        self.reflectance * (wi.z / f64::consts::PI)
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z / f64::consts::PI
+    }
+
+    fn sample(&self, wo: &Vector3<f64>, u: f64, v: f64) -> Option<Vector3<f64>> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        Some(Vector3::random_in_cos_hemisphere(u, v))
+    }
+}
+
+// Oren-Nayar rough diffuse, with roughness the standard deviation of the
+// facet slopes in radians.
+pub struct OrenNayar {
+    reflectance: Vector3<f64>,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    pub fn new(reflectance: Vector3<f64>, roughness: f64) -> Self {
+        let sigma2 = roughness * roughness;
+        Self {
+            reflectance,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+        }
+    }
+}
+
+impl Bxdf for OrenNayar {
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        if !same_hemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+
+        let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+        let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        let cos_phi = if sin_o > 1e-4 && sin_i > 1e-4 {
+            ((wo.x * wi.x + wo.y * wi.y) / (sin_o * sin_i)).max(0.0)
+        } else {
+            0.0
+        };
+
+        // α is the larger of the two angles from the normal, β the smaller
+        let (sin_alpha, tan_beta) = if wi.z < wo.z {
+            (sin_i, sin_o / wo.z)
+        } else {
+            (sin_o, sin_i / wi.z)
+        };
+
+        let scale = self.a + self.b * cos_phi * sin_alpha * tan_beta;
+        self.reflectance * (scale * wi.z / f64::consts::PI)
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z / f64::consts::PI
+    }
+
+    fn sample(&self, wo: &Vector3<f64>, u: f64, v: f64) -> Option<Vector3<f64>> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        Some(Vector3::random_in_cos_hemisphere(u, v))
+    }
+}
+
+// Retro and grazing reflection from fibres (the "Charlie" sheen of Estevez
+// and Kulla) for velvet and cloth.
+pub struct Sheen {
+    color: Vector3<f64>,
+    alpha: f64,
+}
+
+impl Sheen {
+    pub fn new(color: Vector3<f64>, roughness: f64) -> Self {
+        Self {
+            color,
+            alpha: roughness.clamp(0.05, 1.0),
+        }
+    }
+
+    fn distribution(&self, m: &Vector3<f64>) -> f64 {
+        let inverse = 1.0 / self.alpha;
+        let sin = (1.0 - m.z * m.z).max(0.0).sqrt();
+        (2.0 + inverse) * sin.powf(inverse) / (2.0 * f64::consts::PI)
+    }
+}
+
+impl Bxdf for Sheen {
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        if !same_hemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+        let m = (wo + wi).normalize();
+        let visibility = 1.0 / (4.0 * (wi.z + wo.z - wi.z * wo.z));
+        self.color * (self.distribution(&m) * visibility * wi.z)
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z / f64::consts::PI
+    }
+
+    fn sample(&self, wo: &Vector3<f64>, u: f64, v: f64) -> Option<Vector3<f64>> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        Some(Vector3::random_in_cos_hemisphere(u, v))
+    }
+}
+
+// Adds lobes together, sampling each with equal probability.
+pub struct Combined {
+    lobes: Vec<Arc<dyn Bxdf>>,
+}
+
+impl Combined {
+    pub fn new(lobes: Vec<Arc<dyn Bxdf>>) -> Self {
+        assert!(!lobes.is_empty());
+        Self { lobes }
+    }
+}
+
+impl Bxdf for Combined {
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        self.lobes
+            .iter()
+            .fold(Vector3::zeros(), |total, lobe| total + lobe.eval(wo, wi))
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        let total = self.lobes.iter().map(|lobe| lobe.pdf(wo, wi)).sum::<f64>();
+        total / self.lobes.len() as f64
+    }
+
+    fn sample(&self, wo: &Vector3<f64>, u: f64, v: f64) -> Option<Vector3<f64>> {
+        let scaled = u * self.lobes.len() as f64;
+        let index = (scaled as usize).min(self.lobes.len() - 1);
+        self.lobes[index].sample(wo, scaled - index as f64, v)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum MicrofacetFresnel {
+    Dielectric(f64),
+    Conductor(Conductor),
+}
+
+impl MicrofacetFresnel {
+    fn reflectance(&self, cos_incident: f64) -> Vector3<f64> {
+        match self {
+            MicrofacetFresnel::Dielectric(index) => {
+                Vector3::repeat(fresnel::dielectric(cos_incident, 1.0, *index))
+            }
+            MicrofacetFresnel::Conductor(conductor) => conductor.reflectance(cos_incident, 1.0),
+        }
+    }
+}
+
+// GGX microfacet reflection with a height correlated masking term, sampled
+// from the distribution of visible normals.
+pub struct Microfacet {
+    alpha_x: f64,
+    alpha_y: f64,
+    rotation: (f64, f64),
+    fresnel: MicrofacetFresnel,
+}
+
+impl Microfacet {
+    pub fn new(roughness: f64, fresnel: MicrofacetFresnel) -> Self {
+        Self::anisotropic(roughness, roughness, 0.0, fresnel)
+    }
+
+    // Separate roughness along the tangent and bitangent, with the tangent
+    // turned about the normal by `rotation` radians, e.g. for brushed metal.
+    pub fn anisotropic(
+        roughness_u: f64,
+        roughness_v: f64,
+        rotation: f64,
+        fresnel: MicrofacetFresnel,
+    ) -> Self {
+        Self {
+            alpha_x: roughness_to_alpha(roughness_u),
+            alpha_y: roughness_to_alpha(roughness_v),
+            rotation: rotation.sin_cos(),
+            fresnel,
+        }
+    }
+
+    // between the shading frame and one aligned with the rotated tangent
+    fn facet_frame(&self, w: &Vector3<f64>) -> Vector3<f64> {
+        let (sin, cos) = self.rotation;
+        Vector3::new(cos * w.x + sin * w.y, cos * w.y - sin * w.x, w.z)
+    }
+
+    fn shading_frame(&self, w: &Vector3<f64>) -> Vector3<f64> {
+        let (sin, cos) = self.rotation;
+        Vector3::new(cos * w.x - sin * w.y, sin * w.x + cos * w.y, w.z)
+    }
+
+    fn distribution(&self, m: &Vector3<f64>) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let (ax, ay) = (self.alpha_x, self.alpha_y);
+        let e = (m.x / ax).powi(2) + (m.y / ay).powi(2) + m.z * m.z;
+        1.0 / (f64::consts::PI * ax * ay * e * e)
+    }
+
+    fn lambda(&self, w: &Vector3<f64>) -> f64 {
+        let tan2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        ((1.0 + tan2).sqrt() - 1.0) / 2.0
+    }
+
+    fn visible_normal(&self, wo: &Vector3<f64>, u: f64, v: f64) -> Vector3<f64> {
+        let vh = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+        let length_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length_squared > 0.0 {
+            Vector3::new(-vh.y, vh.x, 0.0) / length_squared.sqrt()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(&t1);
+
+        let r = u.sqrt();
+        let phi = 2.0 * f64::consts::PI * v;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        Vector3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(0.0)).normalize()
+    }
+}
+
+// squared perceptual roughness, kept away from zero so it stays sampleable
+fn roughness_to_alpha(roughness: f64) -> f64 {
+    (roughness * roughness).max(1e-3)
+}
+
+impl Bxdf for Microfacet {
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        if !same_hemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+        let (wo, wi) = (&self.facet_frame(wo), &self.facet_frame(wi));
+        let m = (wo + wi).normalize();
+        let masking = 1.0 / (1.0 + self.lambda(wo) + self.lambda(wi));
+        self.fresnel.reflectance(wo.dot(&m)) * (self.distribution(&m) * masking / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let (wo, wi) = (&self.facet_frame(wo), &self.facet_frame(wi));
+        let m = (wo + wi).normalize();
+        let masking = 1.0 / (1.0 + self.lambda(wo));
+        masking * self.distribution(&m) / (4.0 * wo.z)
+    }
+
+    fn sample(&self, wo: &Vector3<f64>, u: f64, v: f64) -> Option<Vector3<f64>> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let wo = self.facet_frame(wo);
+        let wi = reflect(&wo, &self.visible_normal(&wo, u, v));
+        if wi.z > 0.0 {
+            Some(self.shading_frame(&wi))
+        } else {
+            None
+        }
+    }
+}
+
+// A dielectric coat over another bxdf. Light reaching the base loses what
+// the coat reflects on the way in and out, plus whatever the coat absorbs
+// along the two refracted paths through its thickness.
+pub struct Layered {
+    coat: Microfacet,
+    index: f64,
+    absorption: Vector3<f64>,
+    thickness: f64,
+    base: Arc<dyn Bxdf>,
+}
+
+impl Layered {
+    pub fn clearcoat(
+        index: f64,
+        roughness: f64,
+        absorption: Vector3<f64>,
+        thickness: f64,
+        base: Arc<dyn Bxdf>,
+    ) -> Self {
+        Self {
+            coat: Microfacet::new(roughness, MicrofacetFresnel::Dielectric(index)),
+            index,
+            absorption,
+            thickness,
+            base,
+        }
+    }
+
+    fn transmission(&self, w: &Vector3<f64>) -> f64 {
+        1.0 - fresnel::dielectric(w.z, 1.0, self.index)
+    }
+
+    fn coat_transmittance(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        let refracted_cos = |w: &Vector3<f64>| {
+            (1.0 - (1.0 - w.z * w.z) / (self.index * self.index)).max(1e-6).sqrt()
+        };
+        let length = self.thickness * (1.0 / refracted_cos(wo) + 1.0 / refracted_cos(wi));
+        self.absorption.map(|sigma| (-sigma * length).exp())
+    }
+
+    // Keeps sampling the coat often enough to resolve its highlight even
+    // at normal incidence where it reflects very little.
+    fn coat_probability(&self, wo: &Vector3<f64>) -> f64 {
+        (1.0 - self.transmission(wo)).clamp(0.25, 0.9)
+    }
+}
+
+impl Bxdf for Layered {
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        if !same_hemisphere(wo, wi) {
+            return Vector3::zeros();
+        }
+        let attenuation = self.transmission(wo) * self.transmission(wi);
+        self.coat.eval(wo, wi)
+            + self
+                .base
+                .eval(wo, wi)
+                .component_mul(&self.coat_transmittance(wo, wi))
+                * attenuation
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        let p = self.coat_probability(wo);
+        p * self.coat.pdf(wo, wi) + (1.0 - p) * self.base.pdf(wo, wi)
+    }
+
+    fn sample(&self, wo: &Vector3<f64>, u: f64, v: f64) -> Option<Vector3<f64>> {
+        let p = self.coat_probability(wo);
+        if u < p {
+            self.coat.sample(wo, u / p, v)
+        } else {
+            self.base.sample(wo, (u - p) / (1.0 - p), v)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn uniform_hemisphere(u: f64, v: f64) -> Vector3<f64> {
+        let z = u;
+        let r = (1.0 - z * z).sqrt();
+        let phi = 2.0 * f64::consts::PI * v;
+        Vector3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    // The pdf integrates to at most one, and sampling it gives the same
+    // estimate of the reflected energy as integrating eval directly.
+    fn assert_consistent(bxdf: &dyn Bxdf, wo: Vector3<f64>) {
+        let n = 200_000;
+        let mut pdf_integral = 0.0;
+        let mut eval_integral = Vector3::zeros();
+        let mut sampled = Vector3::zeros();
+        for _ in 0..n {
+            let wi = uniform_hemisphere(rand::random(), rand::random());
+            pdf_integral += bxdf.pdf(&wo, &wi) * 2.0 * f64::consts::PI;
+            eval_integral += bxdf.eval(&wo, &wi) * 2.0 * f64::consts::PI;
+
+            if let Some(wi) = bxdf.sample(&wo, rand::random(), rand::random()) {
+                let pdf = bxdf.pdf(&wo, &wi);
+                if pdf > 0.0 {
+                    sampled += bxdf.eval(&wo, &wi) / pdf;
+                }
+            }
+        }
+
+        let pdf_integral = pdf_integral / n as f64;
+        assert!(pdf_integral < 1.02, "pdf integrates to {}", pdf_integral);
+        let (eval_integral, sampled) = (eval_integral / n as f64, sampled / n as f64);
+        for c in 0..3 {
+            assert!(
+                (eval_integral[c] - sampled[c]).abs() < 0.02,
+                "{} != {}",
+                eval_integral[c],
+                sampled[c]
+            );
+        }
+    }
+
+    #[test]
+    fn lambertian_is_consistent() {
+        let wo = Vector3::new(0.3, 0.2, 0.9).normalize();
+        assert_consistent(&Lambertian::new(Vector3::new(0.8, 0.5, 0.2)), wo);
+    }
+
+    #[test]
+    fn oren_nayar_is_consistent_and_lambertian_when_smooth() {
+        let wo = Vector3::new(0.5, 0.1, 0.6).normalize();
+        let color = Vector3::new(0.8, 0.5, 0.2);
+        assert_consistent(&OrenNayar::new(color, 0.6), wo);
+
+        let wi = Vector3::new(-0.2, 0.4, 0.7).normalize();
+        let smooth = OrenNayar::new(color, 0.0).eval(&wo, &wi);
+        assert!((smooth - Lambertian::new(color).eval(&wo, &wi)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn fabric_is_consistent() {
+        let wo = Vector3::new(0.7, 0.0, 0.3).normalize();
+        let fabric = Combined::new(vec![
+            Arc::new(OrenNayar::new(Vector3::new(0.3, 0.0, 0.1), 0.5)),
+            Arc::new(Sheen::new(Vector3::new(1.0, 0.6, 0.8), 0.3)),
+        ]);
+        assert_consistent(&fabric, wo);
+    }
+
+    #[test]
+    fn microfacet_is_consistent() {
+        let wo = Vector3::new(0.6, -0.1, 0.5).normalize();
+        let gold = Conductor::named("gold").unwrap();
+        assert_consistent(&Microfacet::new(0.5, MicrofacetFresnel::Conductor(gold)), wo);
+    }
+
+    #[test]
+    fn anisotropic_microfacet_is_consistent() {
+        let wo = Vector3::new(0.3, 0.5, 0.4).normalize();
+        let aluminium = MicrofacetFresnel::Conductor(Conductor::named("Al").unwrap());
+        assert_consistent(&Microfacet::anisotropic(0.2, 0.7, 0.4, aluminium), wo);
+    }
+
+    #[test]
+    fn rotating_a_quarter_turn_swaps_the_roughness_axes() {
+        let fresnel = MicrofacetFresnel::Dielectric(1.5);
+        let along = Microfacet::anisotropic(0.2, 0.6, 0.0, fresnel);
+        let across = Microfacet::anisotropic(0.6, 0.2, f64::consts::FRAC_PI_2, fresnel);
+        let wo = Vector3::new(0.4, -0.3, 0.8).normalize();
+        let wi = Vector3::new(-0.1, 0.5, 0.6).normalize();
+        assert!((along.eval(&wo, &wi) - across.eval(&wo, &wi)).norm() < 1e-9);
+        assert!((along.pdf(&wo, &wi) - across.pdf(&wo, &wi)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clearcoat_is_consistent_and_loses_energy_to_the_coat() {
+        let wo = Vector3::new(0.2, 0.4, 0.7).normalize();
+        let base: Arc<dyn Bxdf> = Arc::new(Lambertian::new(Vector3::new(0.9, 0.1, 0.1)));
+        let layered = Layered::clearcoat(1.5, 0.4, Vector3::new(0.0, 0.5, 1.0), 0.2, base.clone());
+        assert_consistent(&layered, wo);
+
+        let wi = Vector3::new(-0.3, 0.1, 0.8).normalize();
+        let under_coat = layered.eval(&wo, &wi) - layered.coat.eval(&wo, &wi);
+        let bare = base.eval(&wo, &wi);
+        for c in 0..3 {
+            assert!(under_coat[c] < bare[c]);
+        }
+    }
+}
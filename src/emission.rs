@@ -0,0 +1,103 @@
+use crate::spectrum::{self, MAX_WAVELENGTH, MIN_WAVELENGTH};
+use nalgebra::Vector3;
+use std::f64;
+
+// Emitted radiance is measured in nits (cd/m²), so an emitter whose rgb has a
+// luminance of 100 is as bright as a 100 nit display.
+
+const PLANCK: f64 = 6.62607015e-34;
+const LIGHT_SPEED: f64 = 299_792_458.0;
+const BOLTZMANN: f64 = 1.380649e-23;
+const STEFAN_BOLTZMANN: f64 = 5.670374419e-8;
+
+// lumens per watt of light at the peak of the photopic response
+const MAX_EFFICACY: f64 = 683.0;
+
+// integration steps across the visible spectrum
+const STEPS: usize = 400;
+
+// spectral radiance of a blackbody in W / (sr m² m), wavelength in nm
+fn planck(wavelength: f64, kelvin: f64) -> f64 {
+    let metres = wavelength * 1e-9;
+    2.0 * PLANCK * LIGHT_SPEED * LIGHT_SPEED
+        / metres.powi(5)
+        / ((PLANCK * LIGHT_SPEED / (metres * BOLTZMANN * kelvin)).exp() - 1.0)
+}
+
+fn visible_integral(f: impl Fn(f64) -> Vector3<f64>) -> Vector3<f64> {
+    let step = (MAX_WAVELENGTH - MIN_WAVELENGTH) / STEPS as f64;
+    (0..STEPS)
+        .map(|i| f(MIN_WAVELENGTH + (i as f64 + 0.5) * step) * step)
+        .fold(Vector3::zeros(), |total, v| total + v)
+}
+
+pub fn luminance(rgb: &Vector3<f64>) -> f64 {
+    0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z
+}
+
+// colour of a blackbody at a temperature, with a luminance of one
+pub fn blackbody(kelvin: f64) -> Vector3<f64> {
+    let rgb = visible_integral(|wavelength| {
+        spectrum::wavelength_to_rgb(wavelength) * planck(wavelength, kelvin)
+    });
+    rgb / luminance(&rgb)
+}
+
+// lumens given off per watt radiated by a blackbody, counting the infrared
+// and ultraviolet it wastes
+pub fn luminous_efficacy(kelvin: f64) -> f64 {
+    let visible = visible_integral(|wavelength| {
+        Vector3::repeat(spectrum::cie_xyz(wavelength).y * planck(wavelength, kelvin) * 1e-9)
+    });
+    let total = STEFAN_BOLTZMANN * kelvin.powi(4) / f64::consts::PI;
+    MAX_EFFICACY * visible.x / total
+}
+
+// radiance of a blackbody emitter with the given luminance
+pub fn nits(kelvin: f64, luminance: f64) -> Vector3<f64> {
+    blackbody(kelvin) * luminance
+}
+
+// Radiance of a diffuse blackbody emitter with the given surface area that
+// radiates the given power, e.g. an incandescent bulb of that wattage.
+pub fn watts(kelvin: f64, power: f64, area: f64) -> Vector3<f64> {
+    let lumens = power * luminous_efficacy(kelvin);
+    nits(kelvin, lumens / (f64::consts::PI * area))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blackbodies_warm_up_as_they_cool() {
+        let candle = blackbody(1900.0);
+        let daylight = blackbody(6500.0);
+        let sky = blackbody(12000.0);
+        assert!(candle.x > candle.y && candle.y > candle.z);
+        assert!(sky.z > sky.x);
+        assert!(candle.x / candle.z > daylight.x / daylight.z);
+        for colour in [candle, daylight, sky].iter() {
+            assert!((luminance(colour) - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn efficacy_matches_incandescent_light() {
+        // a blackbody is most efficient at around 6600K, at roughly 95 lm/W
+        assert!((luminous_efficacy(6600.0) - 95.0).abs() < 5.0);
+        assert!(luminous_efficacy(6600.0) > luminous_efficacy(4000.0));
+        assert!(luminous_efficacy(6600.0) > luminous_efficacy(10000.0));
+
+        // and a 100W tungsten filament gives off around 1500 lumens
+        let lumens = 100.0 * luminous_efficacy(2800.0);
+        assert!(lumens > 1200.0 && lumens < 1800.0);
+    }
+
+    #[test]
+    fn power_spreads_over_the_surface() {
+        let small = watts(3000.0, 60.0, 1.0);
+        let large = watts(3000.0, 60.0, 4.0);
+        assert!((luminance(&small) / luminance(&large) - 4.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::emission;
+use crate::image::Image;
+use nalgebra::{Point2, Point3, Vector3};
+use std::f64;
+use std::sync::Arc;
+
+// share of samples drawn through the portals when there are any, the rest
+// following the map so that a sun seen through a window is still found
+const PORTAL_SHARE: f64 = 0.5;
+
+// Light arriving from every direction, read from an equirectangular map whose
+// top row looks straight up. Directions are picked in proportion to the
+// luminance the map gives them, so that small bright features such as the sun
+// or a softbox are found with little noise. Rooms lit through their windows
+// can mark the openings with portals, so that directions are drawn towards
+// them rather than at walls that hide the sky.
+pub struct Environment {
+    image: Arc<Image>,
+    rotation: f64,
+    intensity: f64,
+    rows: Distribution,
+    columns: Vec<Distribution>,
+    portals: Vec<Portal>,
+}
+
+// An opening onto the environment, the parallelogram spanned by two edges
+// from a corner. Portals may be seen from either side.
+pub struct Portal {
+    corner: Point3<f64>,
+    edges: [Vector3<f64>; 2],
+    // not normalized, its length being the area
+    normal: Vector3<f64>,
+}
+
+impl Environment {
+    // the map is turned about the vertical axis by the rotation, in radians,
+    // and its radiance scaled by the intensity
+    pub fn new(image: Arc<Image>, rotation: f64, intensity: f64) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let columns = (0..height)
+            .map(|y| {
+                // rows near the poles cover less of the sphere
+                let sin_theta = (f64::consts::PI * (y as f64 + 0.5) / height as f64).sin();
+                let weights = (0..width)
+                    .map(|x| emission::luminance(&image.pixel(x as isize, y as isize)).max(0.0) * sin_theta)
+                    .collect::<Vec<_>>();
+                Distribution::new(&weights)
+            })
+            .collect::<Vec<_>>();
+        let rows = Distribution::new(&columns.iter().map(|row| row.total).collect::<Vec<_>>());
+
+        Self { image, rotation, intensity, rows, columns, portals: Vec::new() }
+    }
+
+    pub fn with_portals(self, portals: Vec<Portal>) -> Self {
+        Self { portals, ..self }
+    }
+
+    // luminance arriving on a surface, averaged over every way it could face
+    pub fn average_irradiance(&self) -> f64 {
+        // a quarter of the radiance integrated over the sphere, which the
+        // rows cover 2π² times their mean weight
+        f64::consts::PI * f64::consts::PI / 2.0 * self.rows.total * self.intensity
+    }
+
+    pub fn radiance(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        self.image.lookup(self.uv(direction)) * self.intensity
+    }
+
+    // a direction from the point towards the environment, with its radiance
+    // and solid angle density
+    pub fn sample(&self, from: Point3<f64>, u: f64, v: f64) -> (Vector3<f64>, Vector3<f64>, f64) {
+        let direction = if self.portals.is_empty() {
+            self.sample_map(u, v)
+        } else if u < PORTAL_SHARE {
+            match self.sample_portals(from, u / PORTAL_SHARE, v) {
+                Some(direction) => direction,
+                None => return (Vector3::y(), Vector3::zeros(), 0.0),
+            }
+        } else {
+            self.sample_map((u - PORTAL_SHARE) / (1.0 - PORTAL_SHARE), v)
+        };
+        (direction, self.radiance(&direction), self.pdf(from, &direction))
+    }
+
+    // solid angle density of sample picking a direction from the point
+    pub fn pdf(&self, from: Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        if self.portals.is_empty() {
+            return self.map_pdf(direction);
+        }
+        let area = self.portals.iter().map(Portal::area).sum::<f64>();
+        let through = self.portals.iter().map(|portal| portal.pdf(from, direction) * portal.area()).sum::<f64>();
+        PORTAL_SHARE * through / area + (1.0 - PORTAL_SHARE) * self.map_pdf(direction)
+    }
+
+    fn sample_map(&self, u: f64, v: f64) -> Vector3<f64> {
+        let (y, _) = self.rows.sample(u);
+        let row = ((y * self.image.height() as f64) as usize).min(self.image.height() - 1);
+        let (x, _) = self.columns[row].sample(v);
+        self.direction(Point2::new(x, y))
+    }
+
+    fn map_pdf(&self, direction: &Vector3<f64>) -> f64 {
+        let uv = self.uv(direction);
+        let row = ((uv.y * self.image.height() as f64) as usize).min(self.image.height() - 1);
+        solid_angle_pdf(self.rows.pdf(uv.y) * self.columns[row].pdf(uv.x), direction)
+    }
+
+    // a direction through a portal picked in proportion to its area
+    fn sample_portals(&self, from: Point3<f64>, u: f64, v: f64) -> Option<Vector3<f64>> {
+        let area = self.portals.iter().map(Portal::area).sum::<f64>();
+        let mut u = u * area;
+        for portal in &self.portals {
+            if u < portal.area() {
+                return portal.sample(from, u / portal.area(), v);
+            }
+            u -= portal.area();
+        }
+        self.portals.last().and_then(|portal| portal.sample(from, 1.0, v))
+    }
+
+    fn uv(&self, direction: &Vector3<f64>) -> Point2<f64> {
+        let phi = direction.z.atan2(direction.x) - self.rotation;
+        Point2::new(
+            (phi / (2.0 * f64::consts::PI)).rem_euclid(1.0),
+            direction.y.clamp(-1.0, 1.0).acos() / f64::consts::PI,
+        )
+    }
+
+    fn direction(&self, uv: Point2<f64>) -> Vector3<f64> {
+        let phi = 2.0 * f64::consts::PI * uv.x + self.rotation;
+        let theta = f64::consts::PI * uv.y;
+        Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+    }
+}
+
+impl Portal {
+    pub fn new(corner: Point3<f64>, edge_u: Vector3<f64>, edge_v: Vector3<f64>) -> Self {
+        Self { corner, edges: [edge_u, edge_v], normal: edge_u.cross(&edge_v) }
+    }
+
+    fn area(&self) -> f64 {
+        self.normal.norm()
+    }
+
+    // the direction from the point to a spot picked uniformly on the portal,
+    // or none if the point lies in its plane
+    fn sample(&self, from: Point3<f64>, u: f64, v: f64) -> Option<Vector3<f64>> {
+        let offset = self.corner + self.edges[0] * u + self.edges[1] * v - from;
+        if offset.dot(&self.normal) == 0.0 {
+            return None;
+        }
+        Some(offset.normalize())
+    }
+
+    // solid angle density of sample picking the direction, zero when it
+    // misses the portal
+    fn pdf(&self, from: Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        let facing = direction.dot(&self.normal);
+        if facing == 0.0 {
+            return 0.0;
+        }
+        let distance = (self.corner - from).dot(&self.normal) / facing;
+        if distance <= 0.0 {
+            return 0.0;
+        }
+
+        // where the ray crosses the plane, in terms of the edges
+        let local = from + direction * distance - self.corner;
+        let area_squared = self.normal.norm_squared();
+        let u = local.cross(&self.edges[1]).dot(&self.normal) / area_squared;
+        let v = self.edges[0].cross(&local).dot(&self.normal) / area_squared;
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return 0.0;
+        }
+        // the area's density over the cosine it's seen at, which the normal's
+        // length cancels out of
+        distance * distance / facing.abs()
+    }
+}
+
+// the map covers 2π by π radians, squashed together towards the poles
+fn solid_angle_pdf(map_pdf: f64, direction: &Vector3<f64>) -> f64 {
+    let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+    if sin_theta == 0.0 {
+        0.0
+    } else {
+        map_pdf / (2.0 * f64::consts::PI * f64::consts::PI * sin_theta)
+    }
+}
+
+// piecewise constant density over [0, 1), falling back to uniform when every
+// weight is zero
+struct Distribution {
+    cdf: Vec<f64>,
+    total: f64,
+}
+
+impl Distribution {
+    fn new(weights: &[f64]) -> Self {
+        let n = weights.len() as f64;
+        let total = weights.iter().sum::<f64>() / n;
+        let mut cdf = vec![0.0];
+        for (i, weight) in weights.iter().enumerate() {
+            let step = if total > 0.0 { weight / (n * total) } else { 1.0 / n };
+            cdf.push(if i + 1 == weights.len() { 1.0 } else { cdf[i] + step });
+        }
+        Self { cdf, total }
+    }
+
+    fn count(&self) -> usize {
+        self.cdf.len() - 1
+    }
+
+    // a point in [0, 1) with its density
+    fn sample(&self, u: f64) -> (f64, f64) {
+        // skipping bins with no weight
+        let i = (self.cdf.partition_point(|c| *c <= u) - 1).min(self.count() - 1);
+
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 { (u - self.cdf[i]) / width } else { 0.0 };
+        ((i as f64 + offset) / self.count() as f64, width * self.count() as f64)
+    }
+
+    fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        (self.cdf[i + 1] - self.cdf[i]) * self.count() as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a dim sky with one bright pixel for a sun
+    fn sky() -> Environment {
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Vector3::repeat(0.1); width * height];
+        pixels[5 + 2 * width] = Vector3::repeat(500.0);
+        Environment::new(Arc::new(Image::new(width, height, pixels)), 0.7, 2.0)
+    }
+
+    #[test]
+    fn samples_favour_bright_directions() {
+        let environment = sky();
+        let n = 2000;
+        let bright = (0..n)
+            .filter(|i| {
+                let (_, radiance, _) = environment.sample(Point3::origin(), (*i as f64 + 0.5) / n as f64, 0.5);
+                radiance.x > 100.0
+            })
+            .count();
+        assert!(bright > n / 2);
+    }
+
+    #[test]
+    fn sample_densities_match_pdf_and_integrate_to_one() {
+        let environment = sky();
+        for i in 0..50 {
+            let (direction, radiance, pdf) = environment.sample(Point3::origin(), i as f64 / 50.0 + 0.01, 0.37);
+            assert!((direction.norm() - 1.0).abs() < 1e-9);
+            assert!((environment.pdf(Point3::origin(), &direction) / pdf - 1.0).abs() < 1e-6);
+            assert_eq!(radiance, environment.radiance(&direction));
+        }
+
+        // integrate over directions picked uniformly on the sphere
+        let n = 400;
+        let mut total = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let z = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+                let r = (1.0 - z * z).sqrt();
+                let phi = 2.0 * f64::consts::PI * (j as f64 + 0.5) / n as f64;
+                let direction = Vector3::new(r * phi.cos(), z, r * phi.sin());
+                total += environment.pdf(Point3::origin(), &direction) * 4.0 * f64::consts::PI;
+            }
+        }
+        assert!((total / (n * n) as f64 - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn portals_draw_directions_through_the_opening() {
+        // a window in the wall of a room two metres from the point
+        let window = Portal::new(Point3::new(2.0, 0.0, -1.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 2.0));
+        let environment = sky().with_portals(vec![window]);
+        let from = Point3::new(0.0, 0.5, 0.0);
+
+        for i in 0..50 {
+            let (direction, _, pdf) = environment.sample(from, i as f64 / 100.0 + 0.005, 0.63);
+            let crossing = from + direction * (2.0 / direction.x);
+            assert!(direction.x > 0.0);
+            assert!(crossing.y > -1e-9 && crossing.y < 1.0 + 1e-9 && crossing.z.abs() < 1.0 + 1e-9);
+            assert!((environment.pdf(from, &direction) / pdf - 1.0).abs() < 1e-9);
+        }
+
+        let n = 400;
+        let mut total = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let z = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+                let r = (1.0 - z * z).sqrt();
+                let phi = 2.0 * f64::consts::PI * (j as f64 + 0.5) / n as f64;
+                let direction = Vector3::new(r * phi.cos(), z, r * phi.sin());
+                total += environment.pdf(from, &direction) * 4.0 * f64::consts::PI;
+            }
+        }
+        assert!((total / (n * n) as f64 - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn rotation_turns_the_map_about_the_vertical() {
+        let image = Arc::new(Image::new(4, 1, vec![
+            Vector3::repeat(1.0),
+            Vector3::repeat(2.0),
+            Vector3::repeat(3.0),
+            Vector3::repeat(4.0),
+        ]));
+        let still = Environment::new(image.clone(), 0.0, 1.0);
+        let turned = Environment::new(image, f64::consts::FRAC_PI_2, 1.0);
+        let east = Vector3::new(1.0, 0.0, 0.0);
+        let south = Vector3::new(0.0, 0.0, 1.0);
+        assert_eq!(turned.radiance(&south), still.radiance(&east));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::spectrum;
+use crate::texture::Texture;
+use nalgebra::{Complex, ComplexField, Point2, Vector3};
+use std::f64;
+
+// wavelengths averaged over when a path doesn't carry one of its own
+const FILM_WAVELENGTHS: usize = 16;
+
+// Unpolarised reflectance at a smooth boundary between two dielectrics. A
+// negative cosine means the light arrives from the interior side.
+pub fn dielectric(cos_incident: f64, exterior_index: f64, interior_index: f64) -> f64 {
+    let cos_i = cos_incident.clamp(-1.0, 1.0);
+    let (eta_i, eta_t, cos_i) = if cos_i < 0.0 {
+        (interior_index, exterior_index, -cos_i)
+    } else {
+        (exterior_index, interior_index, cos_i)
+    };
+
+    let sin_t = eta_i / eta_t * (1.0 - cos_i * cos_i).max(0.0).sqrt();
+    if sin_t >= 1.0 {
+        return 1.0; // total internal reflection
+    }
+
+    let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
+    let parallel = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    let perpendicular = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+// the index of refraction that gives a reflectance of f0 at normal incidence
+pub fn index_from_reflectance(f0: f64) -> f64 {
+    let r = f0.clamp(0.0, 0.99).sqrt();
+    (1.0 + r) / (1.0 - r)
+}
+
+// Complex index of refraction of a metal, sampled at red, green and blue
+// wavelengths (650, 550 and 450nm).
+#[derive(Copy, Clone)]
+pub struct Conductor {
+    pub eta: Vector3<f64>,
+    pub k: Vector3<f64>,
+}
+
+impl Conductor {
+    pub fn new(eta: Vector3<f64>, k: Vector3<f64>) -> Self {
+        Self { eta, k }
+    }
+
+    // measured metals, by chemical symbol or name
+    pub fn named(name: &str) -> Option<Self> {
+        let (eta, k) = match name.to_lowercase().as_str() {
+            "au" | "gold" => ([0.143, 0.375, 1.442], [3.983, 2.386, 1.603]),
+            "ag" | "silver" => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+            "cu" | "copper" => ([0.200, 0.924, 1.102], [3.913, 2.453, 2.142]),
+            "al" | "aluminium" | "aluminum" => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            "cr" | "chromium" => ([4.370, 2.917, 1.655], [5.206, 4.231, 3.755]),
+            "ti" | "titanium" => ([2.741, 2.542, 2.267], [3.814, 3.435, 3.039]),
+            _ => return None,
+        };
+
+        Some(Self::new(
+            Vector3::from_column_slice(&eta),
+            Vector3::from_column_slice(&k),
+        ))
+    }
+
+    // complex index at a wavelength, interpolated between the rgb samples
+    fn index_at(&self, wavelength: f64) -> Complex<f64> {
+        let (lower, t) = if wavelength < 550.0 {
+            (2, ((wavelength - 450.0) / 100.0).max(0.0))
+        } else {
+            (1, ((wavelength - 550.0) / 100.0).min(1.0))
+        };
+        let upper = lower - 1;
+        Complex::new(
+            self.eta[lower] + (self.eta[upper] - self.eta[lower]) * t,
+            self.k[lower] + (self.k[upper] - self.k[lower]) * t,
+        )
+    }
+
+    // exact reflectance of a smooth metal surface seen from a dielectric
+    pub fn reflectance(&self, cos_incident: f64, exterior_index: f64) -> Vector3<f64> {
+        let cos = cos_incident.clamp(0.0, 1.0);
+        let cos2 = cos * cos;
+        let sin2 = 1.0 - cos2;
+
+        let eta = self.eta / exterior_index;
+        let k = self.k / exterior_index;
+        Vector3::from_fn(|c, _| {
+            let (eta2, k2) = (eta[c] * eta[c], k[c] * k[c]);
+            let t0 = eta2 - k2 - sin2;
+            let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+            let t1 = a2_plus_b2 + cos2;
+            let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+            let t2 = 2.0 * cos * a;
+            let perpendicular = (t1 - t2) / (t1 + t2);
+            let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+            let t4 = t2 * sin2;
+            let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+            (parallel + perpendicular) / 2.0
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Substrate {
+    Dielectric(f64),
+    Conductor(Conductor),
+}
+
+impl Substrate {
+    fn index_at(&self, wavelength: f64) -> Complex<f64> {
+        match self {
+            Substrate::Dielectric(index) => Complex::new(*index, 0.0),
+            Substrate::Conductor(conductor) => conductor.index_at(wavelength),
+        }
+    }
+}
+
+// A thin dielectric coating, with its thickness in nanometres.
+#[derive(Clone)]
+pub struct ThinFilm {
+    thickness: Texture,
+    index: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: Texture, index: f64) -> Self {
+        Self { thickness, index }
+    }
+
+    // Reflectance of the film over a substrate, either at the wavelength the
+    // path carries or averaged over the visible spectrum into rgb.
+    pub fn reflectance(
+        &self,
+        cos_incident: f64,
+        exterior_index: f64,
+        substrate: &Substrate,
+        uv: Point2<f64>,
+        wavelength: Option<f64>,
+    ) -> Vector3<f64> {
+        let thickness = self.thickness.value_mean(uv).max(0.0);
+        let at = |wavelength: f64| {
+            airy(
+                cos_incident,
+                exterior_index,
+                self.index,
+                substrate.index_at(wavelength),
+                thickness,
+                wavelength,
+            )
+        };
+
+        if let Some(wavelength) = wavelength {
+            return Vector3::repeat(at(wavelength));
+        }
+
+        let mut total = Vector3::zeros();
+        let mut weights = Vector3::zeros();
+        for i in 0..FILM_WAVELENGTHS {
+            let wavelength = spectrum::sample_wavelength((i as f64 + 0.5) / FILM_WAVELENGTHS as f64);
+            let weight = spectrum::wavelength_to_rgb(wavelength);
+            total += weight * at(wavelength);
+            weights += weight;
+        }
+        total.component_div(&weights)
+    }
+}
+
+// Airy summation over the reflections inside a film between an exterior
+// dielectric and a possibly absorbing substrate, averaged over polarisations.
+fn airy(
+    cos_incident: f64,
+    exterior_index: f64,
+    film_index: f64,
+    substrate_index: Complex<f64>,
+    thickness: f64,
+    wavelength: f64,
+) -> f64 {
+    let n1 = Complex::new(exterior_index, 0.0);
+    let n2 = Complex::new(film_index, 0.0);
+    let n3 = substrate_index;
+
+    // n cos θ in each layer, from Snell's law with the principal root so
+    // that waves decay into absorbing media
+    let cos1 = cos_incident.clamp(0.0, 1.0);
+    let projected = Complex::new(exterior_index * exterior_index * (1.0 - cos1 * cos1), 0.0);
+    let nc1 = n1 * cos1;
+    let nc2 = (n2 * n2 - projected).sqrt();
+    let nc3 = (n3 * n3 - projected).sqrt();
+
+    let s = |nca: Complex<f64>, ncb: Complex<f64>| (nca - ncb) / (nca + ncb);
+    let p = |na: Complex<f64>, nca: Complex<f64>, nb: Complex<f64>, ncb: Complex<f64>| {
+        (nb * nb * nca - na * na * ncb) / (nb * nb * nca + na * na * ncb)
+    };
+
+    let phase = (Complex::<f64>::i() * nc2 * (4.0 * f64::consts::PI * thickness / wavelength)).exp();
+    let sum = |r12: Complex<f64>, r23: Complex<f64>| {
+        ((r12 + r23 * phase) / (Complex::new(1.0, 0.0) + r12 * r23 * phase)).norm_sqr()
+    };
+
+    let perpendicular = sum(s(nc1, nc2), s(nc2, nc3));
+    let parallel = sum(p(n1, nc1, n2, nc2), p(n2, nc2, n3, nc3));
+    (perpendicular + parallel) / 2.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dielectric_reflectance_at_normal_incidence() {
+        assert!((dielectric(1.0, 1.0, 1.5) - 0.04).abs() < 1e-12);
+        assert!((dielectric(-1.0, 1.0, 1.5) - 0.04).abs() < 1e-12);
+        assert!((dielectric(1.0, 1.0, index_from_reflectance(0.04)) - 0.04).abs() < 1e-12);
+    }
+
+    #[test]
+    fn dielectric_reflects_everything_past_the_critical_angle() {
+        assert_eq!(dielectric(-0.3, 1.0, 1.5), 1.0);
+        assert!(dielectric(0.3, 1.0, 1.5) < 1.0);
+        assert!((dielectric(0.0, 1.0, 1.5) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn conductor_without_absorption_matches_dielectric() {
+        let conductor = Conductor::new(Vector3::repeat(1.5), Vector3::zeros());
+        for cos in [1.0, 0.7, 0.3, 0.05].iter() {
+            let reflectance = conductor.reflectance(*cos, 1.0);
+            assert!((reflectance.x - dielectric(*cos, 1.0, 1.5)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn gold_is_yellow() {
+        let gold = Conductor::named("Au").unwrap().reflectance(1.0, 1.0);
+        assert!(gold.x > 0.9 && gold.x > gold.y && gold.y > gold.z);
+        assert!(Conductor::named("unobtainium").is_none());
+    }
+
+    #[test]
+    fn film_without_thickness_leaves_the_substrate() {
+        let film = ThinFilm::new(Texture::scalar(0.0), 1.33);
+        let uv = Point2::new(0.0, 0.0);
+        let gold = Conductor::named("gold").unwrap();
+        for cos in [1.0, 0.6, 0.2].iter() {
+            let glass = film.reflectance(*cos, 1.0, &Substrate::Dielectric(1.5), uv, Some(500.0));
+            assert!((glass.x - dielectric(*cos, 1.0, 1.5)).abs() < 1e-9);
+
+            let metal = film.reflectance(*cos, 1.0, &Substrate::Conductor(gold), uv, Some(550.0));
+            assert!((metal.x - gold.reflectance(*cos, 1.0).y).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn quarter_wave_coating_cancels_reflection() {
+        let index = 1.5f64.sqrt();
+        let film = ThinFilm::new(Texture::scalar(550.0 / (4.0 * index)), index);
+        let substrate = Substrate::Dielectric(1.5);
+        let uv = Point2::new(0.0, 0.0);
+        assert!(film.reflectance(1.0, 1.0, &substrate, uv, Some(550.0)).x < 1e-9);
+        assert!(film.reflectance(1.0, 1.0, &substrate, uv, Some(420.0)).x > 1e-3);
+    }
+}
@@ -0,0 +1,214 @@
+use std::f64;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+// steps across each angle when integrating the flux
+const FLUX_STEPS: usize = 180;
+
+// The candela distribution of a real fixture from an IES LM-63 photometric
+// file. Only type C photometry is read, the kind used for architectural
+// fixtures: vertical angles run from the nadir straight below the fixture to
+// the zenith above it, and horizontal angles turn about the vertical axis,
+// with the measured quarter, half or whole turn mirrored round the rest. A
+// half turn may run from 0 to 180 degrees or from 90 to 270.
+pub struct IesProfile {
+    vertical: Vec<f64>,
+    horizontal: Vec<f64>,
+    // one row of vertical angles for each horizontal angle
+    candela: Vec<f64>,
+}
+
+impl IesProfile {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .map(str::trim)
+            .find(|line| line.starts_with("TILT="))
+            .ok_or_else(|| invalid("no TILT line"))?;
+
+        let mut numbers = lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|field| !field.is_empty())
+            .map(|field| field.parse::<f64>().map_err(|_| invalid("photometric data should be numeric")));
+        let mut next = || numbers.next().unwrap_or_else(|| Err(invalid("truncated photometric data")));
+
+        match tilt {
+            "TILT=NONE" => {}
+            "TILT=INCLUDE" => {
+                // lamp to luminaire geometry, then pairs of angles and factors
+                next()?;
+                let pairs = next()? as usize;
+                for _ in 0..pairs * 2 {
+                    next()?;
+                }
+            }
+            _ => return Err(invalid("tilt files are not supported")),
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        for _ in 0..4 {
+            // units and luminous opening dimensions
+            next()?;
+        }
+        let ballast_factor = next()?;
+        let _photometric_factor = next()?;
+        let _watts = next()?;
+
+        if photometric_type != 1.0 {
+            return Err(invalid("only type C photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid("no photometric angles"));
+        }
+
+        let mut angles = |count: usize| -> io::Result<Vec<f64>> {
+            let angles = (0..count).map(|_| next().map(f64::to_radians)).collect::<io::Result<Vec<_>>>()?;
+            if angles.windows(2).any(|pair| pair[1] <= pair[0]) {
+                return Err(invalid("photometric angles should increase"));
+            }
+            Ok(angles)
+        };
+        let vertical = angles(vertical_count)?;
+        let horizontal = angles(horizontal_count)?;
+        let candela = (0..vertical_count * horizontal_count)
+            .map(|_| next().map(|value| value * multiplier * ballast_factor))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self { vertical, horizontal, candela })
+    }
+
+    // Intensity towards a direction given by its angle from the nadir and its
+    // horizontal angle, both in radians.
+    pub fn candela(&self, gamma: f64, c: f64) -> f64 {
+        let first = self.vertical[0];
+        let last = self.vertical[self.vertical.len() - 1];
+        if gamma < first || gamma > last {
+            return 0.0;
+        }
+
+        let (v, fv) = bracket(&self.vertical, gamma);
+        let (h, fh) = bracket(&self.horizontal, self.fold(c));
+        let at = |h: usize, v: usize| self.candela[h * self.vertical.len() + v];
+        let row = |h: usize| at(h, v) * (1.0 - fv) + at(h, (v + 1).min(self.vertical.len() - 1)) * fv;
+        row(h) * (1.0 - fh) + row((h + 1).min(self.horizontal.len() - 1)) * fh
+    }
+
+    // mirrors a horizontal angle into the part of the turn that was measured
+    fn fold(&self, c: f64) -> f64 {
+        let c = c.rem_euclid(2.0 * f64::consts::PI).to_degrees();
+        let first = self.horizontal[0].to_degrees().round();
+        let last = self.horizontal[self.horizontal.len() - 1].to_degrees().round();
+        let folded = if last == 0.0 {
+            0.0
+        } else if last == 90.0 {
+            let half = c % 180.0;
+            if half > 90.0 { 180.0 - half } else { half }
+        } else if last == 180.0 {
+            if c > 180.0 { 360.0 - c } else { c }
+        } else if first == 90.0 && last == 270.0 {
+            // mirrored about the plane through 90 and 270
+            if c < 90.0 {
+                180.0 - c
+            } else if c > 270.0 {
+                540.0 - c
+            } else {
+                c
+            }
+        } else {
+            c
+        };
+        folded.to_radians()
+    }
+
+    // luminous flux in lumens, the intensity integrated over every direction
+    pub fn flux(&self) -> f64 {
+        let step = f64::consts::PI / FLUX_STEPS as f64;
+        (0..FLUX_STEPS)
+            .flat_map(|i| (0..2 * FLUX_STEPS).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let gamma = (i as f64 + 0.5) * step;
+                let c = (j as f64 + 0.5) * step;
+                self.candela(gamma, c) * gamma.sin() * step * step
+            })
+            .sum()
+    }
+}
+
+// index of the angle at or below x, and how far x is towards the next one
+fn bracket(angles: &[f64], x: f64) -> (usize, f64) {
+    let i = angles.partition_point(|angle| *angle <= x).max(1) - 1;
+    if i + 1 >= angles.len() {
+        return (i, 0.0);
+    }
+    let t = (x - angles[i]) / (angles[i + 1] - angles[i]);
+    (i, t.clamp(0.0, 1.0))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] downlight
+[MANUFAC] nobody
+TILT=NONE
+1 1000 2.0 3 1 1 2 0.1 0.1 0.0
+1.0 1.0 12
+0 45 90
+0
+500 250
+0
+";
+
+    #[test]
+    fn parses_and_interpolates_a_symmetric_downlight() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+        let (quarter, eighth) = (f64::consts::FRAC_PI_2, f64::consts::FRAC_PI_4);
+        assert_eq!(profile.candela(0.0, 1.0), 1000.0);
+        assert!((profile.candela(eighth / 2.0, 3.0) - 750.0).abs() < 1e-9);
+        assert_eq!(profile.candela(quarter, 0.0), 0.0);
+        assert_eq!(profile.candela(quarter + 0.1, 0.0), 0.0);
+    }
+
+    #[test]
+    fn integrates_an_isotropic_profile_to_4_pi_candela() {
+        let sphere = "TILT=NONE\n1 -1 1 2 1 1 2 0 0 0\n1 1 0\n0 180\n0\n100 100\n";
+        let flux = IesProfile::parse(sphere).unwrap().flux();
+        assert!((flux - 400.0 * f64::consts::PI).abs() < 0.01 * flux);
+    }
+
+    #[test]
+    fn mirrors_bilateral_measurements() {
+        let bilateral = "TILT=NONE\n1 -1 1 1 3 1 2 0 0 0\n1 1 0\n0\n0 90 180\n10 20 30\n";
+        let profile = IesProfile::parse(bilateral).unwrap();
+        let c = |degrees: f64| profile.candela(0.0, degrees.to_radians());
+        assert!((c(90.0) - 20.0).abs() < 1e-9);
+        assert!((c(270.0) - 20.0).abs() < 1e-9);
+        assert!((c(315.0) - 15.0).abs() < 1e-9);
+
+        let other_half = "TILT=NONE\n1 -1 1 1 3 1 2 0 0 0\n1 1 0\n0\n90 180 270\n10 20 30\n";
+        let profile = IesProfile::parse(other_half).unwrap();
+        let c = |degrees: f64| profile.candela(0.0, degrees.to_radians());
+        assert!((c(180.0) - 20.0).abs() < 1e-9);
+        assert!((c(45.0) - 15.0).abs() < 1e-9);
+        assert!((c(0.0) - 20.0).abs() < 1e-9);
+        assert!((c(315.0) - 25.0).abs() < 1e-9);
+
+        assert!(IesProfile::parse("TILT=NONE\n1 -1 1 1 3 2 2 0 0 0\n1 1 0\n0\n0 90 180\n10 20 30\n").is_err());
+        assert!(IesProfile::parse("TILT=NONE\n1 -1 1 1 3 1 2 0 0 0\n1 1 0\n0\n0 90 180\n10 20\n").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use nalgebra::{Point2, Vector3};
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3<f64>>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vector3<f64>>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn load_ppm(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse_ppm(&fs::read(path)?)
+    }
+
+    // binary (P6) portable pixmaps, with values scaled into [0, 1]
+    pub fn parse_ppm(bytes: &[u8]) -> io::Result<Self> {
+        let (fields, body) = header(bytes, 4, "ppm")?;
+        if fields[0] != "P6" {
+            return Err(invalid("only binary ppm images are supported"));
+        }
+
+        let (width, height, max) = (dimension(&fields[1])?, dimension(&fields[2])?, dimension(&fields[3])?);
+        let sample_size = if max > 255 { 2 } else { 1 };
+        if body.len() < width * height * 3 * sample_size {
+            return Err(invalid("ppm data does not match its dimensions"));
+        }
+
+        let sample = |i: usize| {
+            let value = if sample_size == 2 {
+                u16::from_be_bytes([body[i * 2], body[i * 2 + 1]]) as f64
+            } else {
+                f64::from(body[i])
+            };
+            value / max as f64
+        };
+
+        let pixels = (0..width * height)
+            .map(|i| Vector3::new(sample(i * 3), sample(i * 3 + 1), sample(i * 3 + 2)))
+            .collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn load_pfm(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse_pfm(&fs::read(path)?)
+    }
+
+    // Portable float maps, in colour (PF) or greyscale (Pf). A negative scale
+    // marks little endian samples, and rows run from the bottom up.
+    pub fn parse_pfm(bytes: &[u8]) -> io::Result<Self> {
+        let (fields, body) = header(bytes, 4, "pfm")?;
+        let channels = match fields[0].as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid("not a pfm image")),
+        };
+
+        let (width, height) = (dimension(&fields[1])?, dimension(&fields[2])?);
+        let scale = fields[3].parse::<f64>().map_err(|_| invalid("bad pfm header"))?;
+        if body.len() < width * height * channels * 4 {
+            return Err(invalid("pfm data does not match its dimensions"));
+        }
+
+        let sample = |i: usize| {
+            let word = [body[i * 4], body[i * 4 + 1], body[i * 4 + 2], body[i * 4 + 3]];
+            let value = if scale < 0.0 { f32::from_le_bytes(word) } else { f32::from_be_bytes(word) };
+            f64::from(value) * scale.abs()
+        };
+
+        let pixels = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, height - 1 - i / width);
+                let first = (x + y * width) * channels;
+                if channels == 3 {
+                    Vector3::new(sample(first), sample(first + 1), sample(first + 2))
+                } else {
+                    Vector3::repeat(sample(first))
+                }
+            })
+            .collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn load_hdr(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse_hdr(&fs::read(path)?)
+    }
+
+    // Radiance rgbe images, flat or run length encoded, stored top down.
+    pub fn parse_hdr(bytes: &[u8]) -> io::Result<Self> {
+        let mut lines = bytes.split(|b| *b == b'\n');
+        let mut position = 0;
+        let mut line = |position: &mut usize| {
+            lines.next().map(|line| {
+                *position += line.len() + 1;
+                String::from_utf8_lossy(line).into_owned()
+            })
+        };
+
+        match line(&mut position) {
+            Some(ref magic) if magic.starts_with("#?") => {}
+            _ => return Err(invalid("not a radiance hdr image")),
+        }
+        loop {
+            match line(&mut position) {
+                None => return Err(invalid("truncated hdr header")),
+                Some(ref setting) if setting.trim().is_empty() => break,
+                Some(ref setting) if setting.starts_with("FORMAT=") && setting.trim() != "FORMAT=32-bit_rle_rgbe" => {
+                    return Err(invalid("only rgbe hdr images are supported"));
+                }
+                _ => {}
+            }
+        }
+
+        let resolution = line(&mut position).unwrap_or_default();
+        let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (dimension(width)?, dimension(height)?),
+            _ => return Err(invalid("only top down, left to right hdr images are supported")),
+        };
+
+        let mut data = bytes[position.min(bytes.len())..].iter().copied();
+        let mut next = || data.next().ok_or_else(|| invalid("hdr data does not match its dimensions"));
+        let mut rgbe = vec![[0u8; 4]; width * height];
+        for row in rgbe.chunks_mut(width) {
+            let start = [next()?, next()?, next()?, next()?];
+            let encoded = (8..32768).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] < 128;
+            if !encoded {
+                row[0] = start;
+                for pixel in row.iter_mut().skip(1) {
+                    *pixel = [next()?, next()?, next()?, next()?];
+                }
+                continue;
+            }
+
+            if (usize::from(start[2]) << 8 | usize::from(start[3])) != width {
+                return Err(invalid("hdr scanline does not match its width"));
+            }
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = usize::from(next()?);
+                    let (run, length) = if count > 128 { (true, count - 128) } else { (false, count) };
+                    if length == 0 || x + length > width {
+                        return Err(invalid("bad hdr run length"));
+                    }
+                    let repeated = if run { next()? } else { 0 };
+                    for pixel in row[x..x + length].iter_mut() {
+                        pixel[channel] = if run { repeated } else { next()? };
+                    }
+                    x += length;
+                }
+            }
+        }
+
+        let pixels = rgbe
+            .iter()
+            .map(|&[r, g, b, e]| {
+                if e == 0 {
+                    Vector3::zeros()
+                } else {
+                    let scale = 2f64.powi(i32::from(e) - 136);
+                    Vector3::new(f64::from(r), f64::from(g), f64::from(b)) * scale
+                }
+            })
+            .collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // bilinearly filtered lookup that wraps horizontally and clamps vertically
+    pub fn lookup(&self, uv: Point2<f64>) -> Vector3<f64> {
+        let x = uv.x * self.width as f64 - 0.5;
+        let y = uv.y * self.height as f64 - 0.5;
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+        let (x, y) = (x.floor() as isize, y.floor() as isize);
+
+        self.pixel(x, y) * ((1.0 - fx) * (1.0 - fy))
+            + self.pixel(x + 1, y) * (fx * (1.0 - fy))
+            + self.pixel(x, y + 1) * ((1.0 - fx) * fy)
+            + self.pixel(x + 1, y + 1) * (fx * fy)
+    }
+
+    pub fn pixel(&self, x: isize, y: isize) -> Vector3<f64> {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[x + y * self.width]
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn dimension(field: &str) -> io::Result<usize> {
+    field
+        .parse::<usize>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| invalid("bad image dimensions"))
+}
+
+// whitespace separated header fields, skipping comments, and the samples
+// after the single whitespace byte that follows them
+fn header<'a>(bytes: &'a [u8], count: usize, format: &str) -> io::Result<(Vec<String>, &'a [u8])> {
+    let mut position = 0;
+    let mut fields = Vec::new();
+    while fields.len() < count {
+        while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position < bytes.len() && bytes[position] == b'#' {
+            while position < bytes.len() && bytes[position] != b'\n' {
+                position += 1;
+            }
+            continue;
+        }
+
+        let start = position;
+        while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            return Err(invalid(&format!("truncated {} header", format)));
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..position]).into_owned());
+    }
+
+    Ok((fields, &bytes[(position + 1).min(bytes.len())..]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_binary_ppm_with_comments() {
+        let mut bytes = b"P6\n# a comment\n2 1\n255\n".to_vec();
+        bytes.extend_from_slice(&[255, 0, 0, 0, 51, 255]);
+
+        let image = Image::parse_ppm(&bytes).unwrap();
+        assert_eq!(image.lookup(Point2::new(0.25, 0.5)), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(image.lookup(Point2::new(0.75, 0.5)), Vector3::new(0.0, 0.2, 1.0));
+    }
+
+    #[test]
+    fn rejects_ascii_ppm() {
+        assert!(Image::parse_ppm(b"P3\n1 1\n255\n0 0 0\n").is_err());
+    }
+
+    #[test]
+    fn parses_pfm_from_the_bottom_up() {
+        let mut bytes = b"PF\n1 2\n-2.0\n".to_vec();
+        for value in [0.5f32, 0.25, 0.0, 4.0, 8.0, 16.0].iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let image = Image::parse_pfm(&bytes).unwrap();
+        assert_eq!(image.pixel(0, 0), Vector3::new(8.0, 16.0, 32.0));
+        assert_eq!(image.pixel(0, 1), Vector3::new(1.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn parses_flat_and_run_length_encoded_hdr() {
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        let mut flat = header.clone();
+        flat.extend_from_slice(&[128, 64, 0, 129, 128, 128, 128, 128]);
+        let image = Image::parse_hdr(&flat).unwrap();
+        assert_eq!(image.pixel(0, 0), Vector3::new(1.0, 0.5, 0.0));
+        assert_eq!(image.pixel(1, 0), Vector3::new(0.5, 0.5, 0.5));
+
+        // eight pixels, each channel a run or a literal stretch
+        let mut encoded = b"#?RADIANCE\n\n-Y 1 +X 8\n".to_vec();
+        encoded.extend_from_slice(&[2, 2, 0, 8]);
+        encoded.extend_from_slice(&[136, 128]);
+        encoded.extend_from_slice(&[4, 0, 0, 0, 0, 132, 64]);
+        encoded.extend_from_slice(&[136, 0]);
+        encoded.extend_from_slice(&[136, 129]);
+        let image = Image::parse_hdr(&encoded).unwrap();
+        assert_eq!(image.pixel(0, 0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(image.pixel(7, 0), Vector3::new(1.0, 0.5, 0.0));
+
+        assert!(Image::parse_hdr(b"#?RADIANCE\n\n+Y 1 +X 1\n\0\0\0\0").is_err());
+        assert!(Image::parse_hdr(&header).is_err());
+    }
+}
@@ -1,9 +1,24 @@
+mod background;
+mod bxdf;
 mod camera;
+mod emission;
+mod environment;
+mod fresnel;
+mod ies;
+mod image;
+mod light;
+mod light_sampler;
 mod material;
+pub mod material_library;
+mod medium;
 mod ray;
 mod scene;
+mod sky;
 pub mod sensor;
 mod sphere;
+mod spectrum;
 mod onb;
+mod texture;
+mod volume;
 pub mod scene_loader;
 pub mod tracer;
\ No newline at end of file
@@ -0,0 +1,320 @@
+use crate::emission;
+use crate::environment::Environment;
+use crate::ies::IesProfile;
+use crate::light_sampler::Extent;
+use crate::onb::OrthonormalBasis;
+use nalgebra::{Point3, Vector3};
+use std::f64;
+use std::sync::Arc;
+
+// Lights with no surface in the scene. Point and spot intensities are in
+// candela and directional irradiance in lux, so that they light surfaces on
+// the same scale as emitters measured in nits. A point or spot light with a
+// photometric profile has its intensity scaled by the profile's candela, and
+// keeps the direction its profile's C0 plane points in.
+#[derive(Clone)]
+pub enum Light {
+    Point {
+        position: Point3<f64>,
+        intensity: Vector3<f64>,
+        profile: Option<(Arc<IesProfile>, Vector3<f64>)>,
+    },
+    Spot {
+        position: Point3<f64>,
+        direction: Vector3<f64>,
+        intensity: Vector3<f64>,
+        cos_cone: f64,
+        cos_falloff: f64,
+        profile: Option<(Arc<IesProfile>, Vector3<f64>)>,
+    },
+    // direction points towards the light; a light covering no angle is only
+    // found by sampling it
+    Directional {
+        direction: Vector3<f64>,
+        irradiance: Vector3<f64>,
+        cos_radius: f64,
+    },
+    Environment(Arc<Environment>),
+}
+
+// Light arriving at a point from one sampled direction. A delta light has no
+// density, and its radiance is what reaches the point in total.
+pub struct LightSample {
+    pub direction: Vector3<f64>,
+    pub distance: f64,
+    pub radiance: Vector3<f64>,
+    pub pdf: Option<f64>,
+}
+
+impl Light {
+    pub fn point(position: Point3<f64>, intensity: Vector3<f64>) -> Self {
+        Light::Point { position, intensity, profile: None }
+    }
+
+    // A spotlight lighting the cone within the given half angle (in radians),
+    // fading smoothly to nothing over the falloff angle at its edge.
+    pub fn spot(
+        position: Point3<f64>,
+        direction: Vector3<f64>,
+        intensity: Vector3<f64>,
+        cone_angle: f64,
+        falloff: f64,
+    ) -> Self {
+        Light::Spot {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_cone: cone_angle.cos(),
+            cos_falloff: (cone_angle - falloff).max(0.0).cos(),
+            profile: None,
+        }
+    }
+
+    // Shapes a point or spot light like a measured fixture. The profile's
+    // nadir points straight down from a point light, and along a spotlight,
+    // and its C0 plane turns towards the reference direction.
+    pub fn with_profile(self, photometry: Arc<IesProfile>, c0: Vector3<f64>) -> Self {
+        let profile = Some((photometry, c0));
+        match self {
+            Light::Point { position, intensity, .. } => Light::Point { position, intensity, profile },
+            Light::Spot { position, direction, intensity, cos_cone, cos_falloff, .. } => Light::Spot {
+                position,
+                direction,
+                intensity,
+                cos_cone,
+                cos_falloff,
+                profile,
+            },
+            light => light,
+        }
+    }
+
+    // Light from far away, such as the sun, arriving from the given direction
+    // over a disc of the given angular diameter (in radians).
+    pub fn directional(direction: Vector3<f64>, irradiance: Vector3<f64>, angular_diameter: f64) -> Self {
+        Light::Directional {
+            direction: direction.normalize(),
+            irradiance,
+            cos_radius: (angular_diameter / 2.0).cos(),
+        }
+    }
+
+    pub fn environment(environment: Environment) -> Self {
+        Light::Environment(Arc::new(environment))
+    }
+
+    pub fn extent(&self) -> Extent {
+        match *self {
+            Light::Point { position, intensity, ref profile } => Extent::Local {
+                min: position,
+                max: position,
+                power: match profile {
+                    Some((profile, _)) => profile.flux(),
+                    None => 4.0 * f64::consts::PI,
+                } * emission::luminance(&intensity),
+            },
+            // a profiled spotlight is taken as giving off all of its flux
+            Light::Spot { position, intensity, cos_cone, cos_falloff, ref profile, .. } => Extent::Local {
+                min: position,
+                max: position,
+                power: match profile {
+                    Some((profile, _)) => profile.flux(),
+                    None => f64::consts::PI * (2.0 - cos_cone - cos_falloff),
+                } * emission::luminance(&intensity),
+            },
+            Light::Directional { irradiance, .. } => Extent::Infinite(emission::luminance(&irradiance) / 4.0),
+            Light::Environment(ref environment) => Extent::Infinite(environment.average_irradiance()),
+        }
+    }
+
+    pub fn sample(&self, from: Point3<f64>, u: f64, v: f64) -> LightSample {
+        match *self {
+            Light::Point { position, intensity, ref profile } => {
+                let (direction, distance) = towards(from, position);
+                let shape = candela(profile, Vector3::new(0.0, -1.0, 0.0), -direction);
+                LightSample {
+                    direction,
+                    distance,
+                    radiance: intensity * (shape / (distance * distance)),
+                    pdf: None,
+                }
+            }
+            Light::Spot { position, direction: axis, intensity, cos_cone, cos_falloff, ref profile } => {
+                let (direction, distance) = towards(from, position);
+                let cos_theta = -direction.dot(&axis);
+                let shape = smoothstep(cos_cone, cos_falloff, cos_theta) * candela(profile, axis, -direction);
+                LightSample {
+                    direction,
+                    distance,
+                    radiance: intensity * (shape / (distance * distance)),
+                    pdf: None,
+                }
+            }
+            Light::Directional { direction, irradiance, cos_radius } if cos_radius >= 1.0 => LightSample {
+                direction,
+                distance: f64::INFINITY,
+                radiance: irradiance,
+                pdf: None,
+            },
+            Light::Directional { direction: axis, irradiance, cos_radius } => {
+                let cone = 1.0 - cos_radius;
+                let cos_theta = 1.0 - u * cone;
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * f64::consts::PI * v;
+                let direction = OrthonormalBasis::from_normal(axis).local(Vector3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+                LightSample {
+                    direction,
+                    distance: f64::INFINITY,
+                    radiance: disc_radiance(irradiance, cos_radius),
+                    pdf: Some(1.0 / (2.0 * f64::consts::PI * cone)),
+                }
+            }
+            Light::Environment(ref environment) => {
+                let (direction, radiance, pdf) = environment.sample(from, u, v);
+                LightSample {
+                    direction,
+                    distance: f64::INFINITY,
+                    radiance,
+                    pdf: Some(pdf),
+                }
+            }
+        }
+    }
+
+    // Radiance seen along a ray leaving the scene from a point, with the
+    // density sample would have picked its direction with. Only lights
+    // covering some angle can be seen.
+    pub fn radiance_towards(&self, from: Point3<f64>, direction: &Vector3<f64>) -> Option<(Vector3<f64>, f64)> {
+        match *self {
+            Light::Directional { direction: axis, irradiance, cos_radius }
+                if cos_radius < 1.0 && direction.dot(&axis) >= cos_radius =>
+            {
+                let pdf = 1.0 / (2.0 * f64::consts::PI * (1.0 - cos_radius));
+                Some((disc_radiance(irradiance, cos_radius), pdf))
+            }
+            Light::Environment(ref environment) => {
+                Some((environment.radiance(direction), environment.pdf(from, direction)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// a fixture's relative intensity leaving towards a direction, one without a
+// profile being the same all round
+fn candela(profile: &Option<(Arc<IesProfile>, Vector3<f64>)>, nadir: Vector3<f64>, outgoing: Vector3<f64>) -> f64 {
+    match profile {
+        Some((profile, c0)) => {
+            let local = OrthonormalBasis::from_normal_tangent(nadir, *c0).to_local(outgoing);
+            profile.candela(local.z.clamp(-1.0, 1.0).acos(), local.y.atan2(local.x))
+        }
+        None => 1.0,
+    }
+}
+
+fn towards(from: Point3<f64>, to: Point3<f64>) -> (Vector3<f64>, f64) {
+    let offset = to - from;
+    let distance = offset.norm();
+    (offset / distance, distance)
+}
+
+// radiance of a uniform disc that gives the irradiance when faced head on
+fn disc_radiance(irradiance: Vector3<f64>, cos_radius: f64) -> Vector3<f64> {
+    let sin2_radius = 1.0 - cos_radius * cos_radius;
+    irradiance / (f64::consts::PI * sin2_radius)
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    if edge1 <= edge0 {
+        return if x >= edge0 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn point_lights_fall_off_with_distance_squared() {
+        let light = Light::point(Point3::new(0.0, 4.0, 0.0), Vector3::repeat(100.0));
+        let near = light.sample(Point3::new(0.0, 2.0, 0.0), 0.5, 0.5);
+        let far = light.sample(Point3::origin(), 0.5, 0.5);
+        assert_eq!(near.direction, Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(near.distance, 2.0);
+        assert!(near.pdf.is_none());
+        assert!((near.radiance.x / far.radiance.x - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn spotlights_fade_out_at_the_edge_of_their_cone() {
+        let light = Light::spot(
+            Point3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, -1.0, 0.0),
+            Vector3::repeat(1.0),
+            0.5,
+            0.2,
+        );
+        let at_angle = |angle: f64| {
+            light.sample(Point3::new(angle.tan(), 0.0, 0.0), 0.5, 0.5).radiance.x * (1.0 / angle.cos()).powi(2)
+        };
+        assert!((at_angle(0.0) - 1.0).abs() < 1e-12);
+        assert!((at_angle(0.29) - 1.0).abs() < 1e-12);
+        assert!(at_angle(0.4) > 0.0 && at_angle(0.4) < 1.0);
+        assert_eq!(at_angle(0.51), 0.0);
+    }
+
+    #[test]
+    fn profiles_shape_point_lights_about_their_nadir() {
+        let downlight = IesProfile::parse("TILT=NONE\n1 -1 1 3 1 1 2 0 0 0\n1 1 0\n0 45 90\n0\n100 50 0\n").unwrap();
+        let light = Light::point(Point3::new(0.0, 2.0, 0.0), Vector3::repeat(1.0)).with_profile(Arc::new(downlight), Vector3::new(1.0, 0.0, 0.0));
+        let below = light.sample(Point3::origin(), 0.5, 0.5);
+        assert!((below.radiance.x - 25.0).abs() < 1e-9);
+        let beside = light.sample(Point3::new(2.0, 2.0, 0.0), 0.5, 0.5);
+        assert_eq!(beside.radiance.x, 0.0);
+
+        match light.extent() {
+            Extent::Local { power, .. } => assert!(power > 0.0 && power < 4.0 * f64::consts::PI * 100.0),
+            Extent::Infinite(_) => panic!("point lights have a place"),
+        }
+    }
+
+    #[test]
+    fn profiles_turn_their_c0_plane_towards_the_reference_direction() {
+        let one_sided = Arc::new(IesProfile::parse("TILT=NONE\n1 -1 1 2 2 1 2 0 0 0\n1 1 0\n0 90\n0 180\n0 100 0 0\n").unwrap());
+        let at = |c0: Vector3<f64>, z: f64| {
+            let light = Light::point(Point3::origin(), Vector3::repeat(1.0)).with_profile(one_sided.clone(), c0);
+            light.sample(Point3::new(0.0, 0.0, z), 0.5, 0.5).radiance.x
+        };
+        assert!((at(Vector3::new(0.0, 0.0, 1.0), 2.0) - 25.0).abs() < 1e-9);
+        assert_eq!(at(Vector3::new(0.0, 0.0, 1.0), -2.0), 0.0);
+        assert!((at(Vector3::new(0.0, 0.0, -1.0), -2.0) - 25.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sun_discs_give_the_same_irradiance_as_a_delta_sun() {
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let irradiance = Vector3::repeat(1000.0);
+        let sun = Light::directional(up, irradiance, 0.0093);
+        let delta = Light::directional(up, irradiance, 0.0);
+        assert!(delta.sample(Point3::origin(), 0.5, 0.5).pdf.is_none());
+        assert!(delta.radiance_towards(Point3::origin(), &up).is_none());
+
+        let n = 1000;
+        let mut total = 0.0;
+        for i in 0..n {
+            let sample = sun.sample(Point3::origin(), (i as f64 + 0.5) / n as f64, 0.37);
+            assert!(sample.direction.dot(&up) > 0.99);
+            let (radiance, pdf) = sun.radiance_towards(Point3::origin(), &sample.direction).unwrap();
+            assert_eq!(Some(pdf), sample.pdf);
+            total += radiance.x * sample.direction.dot(&up) / pdf;
+        }
+        assert!((total / n as f64 - 1000.0).abs() < 1e-3 * 1000.0);
+        assert!(sun.radiance_towards(Point3::origin(), &Vector3::new(1.0, 0.0, 0.0)).is_none());
+    }
+}
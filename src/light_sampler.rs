@@ -0,0 +1,337 @@
+use nalgebra::Point3;
+use std::f64;
+
+// Where a light is and how much it gives off, for choosing between lights.
+// Lights at infinity have no place, and are weighed by the irradiance they
+// give averaged over every orientation of the surface.
+#[derive(Copy, Clone)]
+pub enum Extent {
+    Local { min: Point3<f64>, max: Point3<f64>, power: f64 },
+    Infinite(f64),
+}
+
+// Chooses a light for a point in proportion to how much it is likely to light
+// it. Local lights are found through a hierarchy over their bounds, weighing
+// each branch by its power over its squared distance, and lights at infinity
+// are picked by their weight from an alias table. The local lights together
+// are weighed against those at infinity by the irradiance the whole hierarchy
+// is likely to give the point.
+pub struct LightSampler {
+    slots: Vec<Slot>,
+    infinite: Vec<usize>,
+    table: Option<AliasTable>,
+    infinite_weight: f64,
+    nodes: Vec<Node>,
+}
+
+#[derive(Copy, Clone)]
+enum Slot {
+    // the left or right turns taken from the root to reach the light
+    Local { trail: u64, depth: u32 },
+    Infinite(usize),
+    // lights with no power are never picked
+    Unlit,
+}
+
+struct Node {
+    min: Point3<f64>,
+    max: Point3<f64>,
+    power: f64,
+    // the first child follows its parent, so interior nodes keep the second
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Leaf(usize),
+    Interior(usize),
+}
+
+impl LightSampler {
+    pub fn new(extents: &[Extent]) -> Self {
+        let mut slots = vec![Slot::Unlit; extents.len()];
+        let mut infinite = Vec::new();
+        let mut weights = Vec::new();
+        let mut local = Vec::new();
+        for (i, extent) in extents.iter().enumerate() {
+            match *extent {
+                Extent::Local { min, max, power } if power > 0.0 => local.push((i, min, max, power)),
+                Extent::Local { .. } => {}
+                Extent::Infinite(weight) => {
+                    slots[i] = Slot::Infinite(infinite.len());
+                    infinite.push(i);
+                    weights.push(weight);
+                }
+            }
+        }
+
+        let mut nodes = Vec::new();
+        build(&mut local, 0, 0, &mut nodes, &mut slots);
+
+        let table = if infinite.is_empty() { None } else { Some(AliasTable::new(&weights)) };
+        let infinite_weight = weights.iter().sum();
+        Self { slots, infinite, table, infinite_weight, nodes }
+    }
+
+    // Chance of picking the local lights as a whole for the point. A light
+    // giving off all of its power evenly lights a surface facing it with
+    // power / 4πd², and a quarter of that averaged over every orientation,
+    // as the weights of lights at infinity are.
+    fn local_probability(&self, p: Point3<f64>) -> f64 {
+        if self.nodes.is_empty() {
+            return 0.0;
+        }
+        if self.infinite.is_empty() {
+            return 1.0;
+        }
+        let local = self.nodes[0].importance(p) / (16.0 * f64::consts::PI);
+        if local + self.infinite_weight > 0.0 {
+            local / (local + self.infinite_weight)
+        } else {
+            0.5
+        }
+    }
+
+    // a light for the point, with the chance of having picked it
+    pub fn sample(&self, p: Point3<f64>, u: f64) -> Option<(usize, f64)> {
+        let local = self.local_probability(p);
+        if u >= local {
+            let table = self.table.as_ref()?;
+            let (i, probability) = table.sample((u - local) / (1.0 - local));
+            return Some((self.infinite[i], probability * (1.0 - local)));
+        }
+
+        let mut u = u / local;
+        let mut probability = local;
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(light) => return Some((light, probability)),
+                NodeKind::Interior(second) => {
+                    let left = self.nodes[node + 1].importance(p);
+                    let right = self.nodes[second].importance(p);
+                    if left + right <= 0.0 {
+                        return None;
+                    }
+                    let chance = left / (left + right);
+                    if u < chance {
+                        u /= chance;
+                        probability *= chance;
+                        node += 1;
+                    } else {
+                        u = ((u - chance) / (1.0 - chance)).min(1.0 - f64::EPSILON);
+                        probability *= 1.0 - chance;
+                        node = second;
+                    }
+                }
+            }
+        }
+    }
+
+    // chance of sample picking the light for the point
+    pub fn probability(&self, p: Point3<f64>, light: usize) -> f64 {
+        match self.slots[light] {
+            Slot::Infinite(i) => match self.table {
+                Some(ref table) => table.probability(i) * (1.0 - self.local_probability(p)),
+                None => 0.0,
+            },
+            Slot::Unlit => 0.0,
+            Slot::Local { trail, depth } => {
+                let mut probability = self.local_probability(p);
+                let mut node = 0;
+                for level in 0..depth {
+                    let second = match self.nodes[node].kind {
+                        NodeKind::Interior(second) => second,
+                        NodeKind::Leaf(_) => break,
+                    };
+                    let left = self.nodes[node + 1].importance(p);
+                    let right = self.nodes[second].importance(p);
+                    if left + right <= 0.0 {
+                        return 0.0;
+                    }
+                    if trail >> level & 1 == 0 {
+                        probability *= left / (left + right);
+                        node += 1;
+                    } else {
+                        probability *= right / (left + right);
+                        node = second;
+                    }
+                }
+                probability
+            }
+        }
+    }
+}
+
+impl Node {
+    // power over the squared distance to the bounds, which is never taken as
+    // closer than the bounds are wide so that a point inside them still
+    // weighs the lights there
+    fn importance(&self, p: Point3<f64>) -> f64 {
+        let center = Point3::from((self.min.coords + self.max.coords) / 2.0);
+        let half_diagonal_squared = (self.max - self.min).norm_squared() / 4.0;
+        self.power / (p - center).norm_squared().max(half_diagonal_squared).max(1e-12)
+    }
+}
+
+// Builds the hierarchy depth first, splitting lights at the middle of the
+// axis along which their centers spread the most.
+fn build(
+    lights: &mut [(usize, Point3<f64>, Point3<f64>, f64)],
+    trail: u64,
+    depth: u32,
+    nodes: &mut Vec<Node>,
+    slots: &mut [Slot],
+) {
+    if lights.is_empty() {
+        return;
+    }
+
+    let mut min = lights[0].1;
+    let mut max = lights[0].2;
+    let mut power = 0.0;
+    for (_, low, high, light_power) in lights.iter() {
+        min = Point3::from(min.coords.zip_map(&low.coords, f64::min));
+        max = Point3::from(max.coords.zip_map(&high.coords, f64::max));
+        power += light_power;
+    }
+
+    let index = nodes.len();
+    if lights.len() == 1 {
+        let (light, ..) = lights[0];
+        slots[light] = Slot::Local { trail, depth };
+        nodes.push(Node { min, max, power, kind: NodeKind::Leaf(light) });
+        return;
+    }
+    nodes.push(Node { min, max, power, kind: NodeKind::Leaf(0) });
+
+    let center = |light: &(usize, Point3<f64>, Point3<f64>, f64)| (light.1.coords + light.2.coords) / 2.0;
+    let spread = lights.iter().fold((center(&lights[0]), center(&lights[0])), |(low, high), light| {
+        (low.zip_map(&center(light), f64::min), high.zip_map(&center(light), f64::max))
+    });
+    let axis = (spread.1 - spread.0).imax();
+    lights.sort_by(|a, b| center(a)[axis].partial_cmp(&center(b)[axis]).unwrap());
+
+    let middle = lights.len() / 2;
+    let (left, right) = lights.split_at_mut(middle);
+    build(left, trail, depth + 1, nodes, slots);
+    let second = nodes.len();
+    build(right, trail | 1 << depth, depth + 1, nodes, slots);
+    nodes[index].kind = NodeKind::Interior(second);
+}
+
+// Picks from a fixed set of weights in constant time (Vose's method).
+pub struct AliasTable {
+    probabilities: Vec<f64>,
+    bins: Vec<(f64, usize)>,
+}
+
+impl AliasTable {
+    // weights that are all zero are treated as equal
+    pub fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let total = weights.iter().sum::<f64>();
+        let probabilities = weights
+            .iter()
+            .map(|w| if total > 0.0 { w / total } else { 1.0 / n as f64 })
+            .collect::<Vec<_>>();
+
+        let mut bins = vec![(1.0, 0); n];
+        let scaled = probabilities.iter().map(|p| p * n as f64).collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<_>, Vec<_>) = (0..n).map(|i| (i, scaled[i])).partition(|(_, p)| *p < 1.0);
+        while let (Some((s, p_small)), Some((l, p_large))) = (small.pop(), large.pop()) {
+            bins[s] = (p_small, l);
+            let remaining = p_large + p_small - 1.0;
+            if remaining < 1.0 {
+                small.push((l, remaining));
+            } else {
+                large.push((l, remaining));
+            }
+        }
+        // whatever is left over fills its bin up to rounding
+        for (i, _) in small.into_iter().chain(large) {
+            bins[i] = (1.0, i);
+        }
+
+        Self { probabilities, bins }
+    }
+
+    // an index with the chance of having picked it
+    pub fn sample(&self, u: f64) -> (usize, f64) {
+        let n = self.bins.len();
+        let scaled = u * n as f64;
+        let bin = (scaled as usize).min(n - 1);
+        let (keep, alias) = self.bins[bin];
+        let i = if scaled - (bin as f64) < keep { bin } else { alias };
+        (i, self.probabilities[i])
+    }
+
+    pub fn probability(&self, i: usize) -> f64 {
+        self.probabilities[i]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alias_table_picks_in_proportion_to_weight() {
+        let weights = [1.0, 0.0, 3.0, 6.0];
+        let table = AliasTable::new(&weights);
+        let n = 10000;
+        let mut counts = [0; 4];
+        for i in 0..n {
+            let (picked, probability) = table.sample((i as f64 + 0.5) / n as f64);
+            assert_eq!(probability, weights[picked] / 10.0);
+            counts[picked] += 1;
+        }
+        for (count, weight) in counts.iter().zip(weights.iter()) {
+            assert!((*count as f64 / n as f64 - weight / 10.0).abs() < 1e-3);
+        }
+    }
+
+    fn lamp(x: f64, power: f64) -> Extent {
+        Extent::Local {
+            min: Point3::new(x - 0.5, -0.5, -0.5),
+            max: Point3::new(x + 0.5, 0.5, 0.5),
+            power,
+        }
+    }
+
+    #[test]
+    fn nearby_and_powerful_lights_are_favoured() {
+        let mut extents = (0..1000).map(|i| lamp(i as f64 * 4.0, 1.0)).collect::<Vec<_>>();
+        extents.push(Extent::Infinite(1.0));
+        extents.push(lamp(-20.0, 0.0));
+        let sampler = LightSampler::new(&extents);
+
+        let p = Point3::new(2000.0, 3.0, 0.0);
+        let near = sampler.probability(p, 500);
+        let far = sampler.probability(p, 0);
+        assert!(near > 1000.0 * far);
+        assert_eq!(sampler.probability(p, 1001), 0.0);
+
+        // and probabilities match what sample picks, summing to one
+        let total = (0..extents.len()).map(|i| sampler.probability(p, i)).sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9);
+        for i in 0..200 {
+            let (light, probability) = sampler.sample(p, (i as f64 + 0.5) / 200.0).unwrap();
+            assert!((sampler.probability(p, light) / probability - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn local_lights_are_weighed_against_lights_at_infinity() {
+        // a lamp lighting points a metre away as brightly as the sky does
+        let lamp = Extent::Local { min: Point3::origin(), max: Point3::origin(), power: 16.0 * f64::consts::PI };
+        let sampler = LightSampler::new(&[lamp, Extent::Infinite(1.0)]);
+
+        assert!((sampler.probability(Point3::new(1.0, 0.0, 0.0), 0) - 0.5).abs() < 1e-9);
+        assert!((sampler.probability(Point3::new(0.0, 3.0, 0.0), 1) - 0.9).abs() < 1e-9);
+        assert_eq!(sampler.sample(Point3::new(0.0, 0.0, 3.0), 0.05).unwrap().0, 0);
+        assert_eq!(sampler.sample(Point3::new(0.0, 0.0, 3.0), 0.15).unwrap().0, 1);
+
+        assert_eq!(LightSampler::new(&[lamp]).probability(Point3::new(9.0, 0.0, 0.0), 0), 1.0);
+        assert_eq!(LightSampler::new(&[Extent::Infinite(0.0)]).probability(Point3::origin(), 0), 1.0);
+    }
+}
@@ -1,38 +1,82 @@
-use nalgebra::{geometry::Reflection, Unit, Vector3, Point3};
+use nalgebra::{geometry::Reflection, Unit, Vector3, Point2, Point3};
 use crate::ray::{DirectionExt, Ray};
-use crate::scene::{Scene, Intersection};
+use crate::scene::{Intersection};
 use crate::onb::{OrthonormalBasis};
-use crate::sphere::{Sphere};
+use crate::emission;
+use crate::medium::Medium;
+use crate::spectrum::Ior;
+use crate::fresnel::{self, Conductor, Substrate, ThinFilm};
+use crate::texture::Texture;
+use crate::bxdf::Bxdf;
+use std::sync::Arc;
 use rand;
-use rand::seq::SliceRandom;
 use std::f64;
 
 #[derive(Copy, Clone)]
 pub struct SurfacePoint {
     pub n: Vector3<f64>,
     pub p: Point3<f64>,
+    pub uv: Point2<f64>,
+    pub tangent: Vector3<f64>,
 }
 
 #[derive(Copy, Clone)]
 pub struct SurfaceInteraction {
     pub wo: Vector3<f64>,
-    pub surface: SurfacePoint
+    pub surface: SurfacePoint,
+    pub wavelength: Option<f64>,
+    // index of refraction on the side the normal points to
+    pub exterior_index: f64
 }
 
 pub struct BSDF {
     pub direction: Vector3<f64>,
-    pub signal: Vector3<f64>
+    pub signal: Vector3<f64>,
+    // solid angle density the direction was sampled with, or none for
+    // specular directions that light sampling could never pick
+    pub pdf: Option<f64>
 }
 
-#[derive(Copy, Clone)]
+impl BSDF {
+    fn tinted(self, tint: Vector3<f64>) -> Self {
+        Self {
+            signal: self.signal.component_mul(&tint),
+            ..self
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Material {
     color: Vector3<f64>,
-    refraction: f64,
+    refraction: Ior,
     transparency: f64,
     light: Vector3<f64>,
     frensel: Vector3<f64>,
     metal: f64,
     gloss: f64,
+    medium: Option<Medium>,
+    conductor: Option<Conductor>,
+    thin_film: Option<ThinFilm>,
+    bxdf: Option<Arc<dyn Bxdf>>,
+    priority: u32,
+    opacity: Option<Texture>,
+    matte: Option<Matte>,
+    emission_texture: Option<Texture>,
+    one_sided: bool,
+    falloff: f64,
+    light_group: Option<String>,
+}
+
+// Stand-ins for parts of a photograph the render is composited over, which
+// only change what camera rays see.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Matte {
+    // cuts a hole in the render, and is black to everything else
+    Holdout,
+    // transparent apart from the shadows and reflections the rest of the
+    // scene casts on it, and white diffuse to everything else
+    ShadowCatcher,
 }
 
 impl Material {
@@ -47,258 +91,492 @@ impl Material {
     ) -> Self {
         Self {
             color,
-            refraction,
+            refraction: Ior::Constant(refraction),
             transparency,
             light,
             frensel,
             metal,
             gloss,
+            medium: None,
+            conductor: None,
+            thin_film: None,
+            bxdf: None,
+            priority: 0,
+            opacity: None,
+            matte: None,
+            emission_texture: None,
+            one_sided: false,
+            falloff: 0.0,
+            light_group: None,
         }
     }
 
-    pub fn can_emit(&self) -> bool {
-        self.light.norm() > 0.0
+    // an opaque surface scattering with an arbitrary bxdf, e.g. a layered one
+    pub fn from_bxdf(bxdf: Arc<dyn Bxdf>) -> Self {
+        Self {
+            bxdf: Some(bxdf),
+            ..Self::new(
+                Vector3::new(0.0, 0.0, 0.0),
+                1.0,
+                0.0,
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 0.0),
+                0.0,
+                0.0,
+            )
+        }
     }
 
-    pub fn emit(&self) -> Vector3<f64> {
-        self.light
+    // a metal reflecting with the exact Fresnel term for its complex index
+    pub fn metal(conductor: Conductor, gloss: f64) -> Self {
+        Self {
+            conductor: Some(conductor),
+            ..Self::new(
+                Vector3::new(0.0, 0.0, 0.0),
+                1.0,
+                0.0,
+                Vector3::new(0.0, 0.0, 0.0),
+                conductor.reflectance(1.0, 1.0),
+                1.0,
+                gloss,
+            )
+        }
     }
 
-    pub fn bsdf(
-        &self,
-        scene: &Scene,
-        interaction: SurfaceInteraction,
-        length: f64,
-        u: f64,
-        v: f64
-    ) -> BSDF {
-        if interaction.wo.dot(&interaction.surface.n) > 0f64 {
-            // brdf
-            let mut test = FilteredProbabilityTest::new();
-            if test.or(self.schilck(&interaction).component_average()) {
-                self.reflected(&interaction, u, v)
-            } else if test.or(self.transparency) {
-                self.refracted_entry(&interaction)
-            } else if test.or(self.metal) {
-                self.dead()
-            } else {
-                self.diffused(scene, &interaction, u, v)
-            }
-        } else if let Some(exited) = (-interaction.wo).refraction(
-                &-interaction.surface.n,
-                self.refraction,
-                1.0
-        ) {
-            // btdf
-            self.refracted_exit(exited, length)
-        } else {
-            self.dead()
+    // replaces the constant index of refraction with a wavelength dependent one
+    pub fn with_dispersion(self, refraction: Ior) -> Self {
+        Self {
+            refraction,
+            ..self
         }
     }
 
-    fn dead(&self) -> BSDF {
-        BSDF {
-            direction: Vector3::new(0.0, 0.0, 0.0),
-            signal: Vector3::new(0.0, 0.0, 0.0)
-        }
+    pub fn is_dispersive(&self) -> bool {
+        self.refraction.is_dispersive()
     }
 
-    fn schilck(&self, interaction: &SurfaceInteraction) -> Vector3<f64> {
-        let cos_incident = interaction.wo.dot(&interaction.surface.n);
-        self.frensel + ((Vector3::new(1.0, 1.0, 1.0) - self.frensel) * (1.0 - cos_incident).powf(5.0))
+    // coats the specular lobe with a film, thickness given in nanometres
+    pub fn with_thin_film(self, thickness: Texture, index: f64) -> Self {
+        Self {
+            thin_film: Some(ThinFilm::new(thickness, index)),
+            ..self
+        }
     }
 
-    fn diffused(&self, scene: &Scene, interaction: &SurfaceInteraction, u: f64, v: f64, ) -> BSDF {
-        let cos_component = CosWeightedDiffuse::new(interaction.surface.n, u, v);
-        let mut components: Vec<&dyn Pdf> = vec![&cos_component];
-        let lights = scene.lights();
-        let light_components = lights.iter().map(|light| {
-            LightWeightedDiffuse::new(
-                interaction.surface.p,
-                interaction.surface.n,
-                &light
-            )
-        }).collect::<Vec<_>>();
+    // Cutout mask: rays pass straight through the surface, without
+    // refracting, with probability one minus the opacity.
+    pub fn with_opacity(self, opacity: Texture) -> Self {
+        Self {
+            opacity: Some(opacity),
+            ..self
+        }
+    }
 
-        light_components.iter().fold(&mut components, |acc, light| {
-            acc.push(light);
-            acc
-        });
+    pub fn opacity(&self, uv: Point2<f64>) -> f64 {
+        self.opacity
+            .as_ref()
+            .map_or(1.0, |opacity| opacity.value_mean(uv).clamp(0.0, 1.0))
+    }
 
-        let mix = MixturePdf::new(components);
-        let direction = mix.gen();
-        BSDF {
-            direction,
-            signal: self.color * mix.p(direction)
+    // Where transmissive objects overlap, the one with the highest priority
+    // owns the overlap and surfaces of the others inside it are ignored.
+    pub fn with_priority(self, priority: u32) -> Self {
+        Self {
+            priority,
+            ..self
         }
     }
 
-    fn reflected(&self, interaction: &SurfaceInteraction, u: f64, v: f64) -> BSDF {
-        let mut reflected = -interaction.wo;
-        Reflection::new(Unit::new_normalize(interaction.surface.n), 0.0)
-            .reflect(&mut reflected);
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
 
-        BSDF{
-            direction: Vector3::random_in_cone(
-                &reflected,
-                1.0 - self.gloss,
-                u,
-                v
-            ),
-            signal: Vector3::new(1.0, 1.0, 1.0).lerp(&self.frensel, self.metal)
+    // whether paths can travel inside objects made of this material
+    pub fn is_enclosure(&self) -> bool {
+        self.transparency > 0.0 || self.medium.is_some()
+    }
+
+    pub fn interior_index(&self, wavelength: Option<f64>) -> f64 {
+        self.refraction.at(wavelength)
+    }
+
+    pub fn with_medium(self, medium: Medium) -> Self {
+        Self {
+            medium: Some(medium),
+            ..self
         }
     }
 
-    fn refracted_entry(&self, interaction: &SurfaceInteraction) -> BSDF {
-        BSDF{
-            direction: (-interaction.wo).refraction(
-                &interaction.surface.n,
+    pub fn medium(&self) -> Option<&Medium> {
+        self.medium.as_ref()
+    }
+
+    // a surface that only emits, with radiance in nits
+    pub fn emitter(light: Vector3<f64>) -> Self {
+        Self::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            1.0,
+            1.0,
+            light,
+            Vector3::new(0.0, 0.0, 0.0),
+            0.0,
+            0.0,
+        )
+    }
+
+    pub fn holdout() -> Self {
+        Self {
+            matte: Some(Matte::Holdout),
+            ..Self::new(
+                Vector3::new(0.0, 0.0, 0.0),
                 1.0,
-                self.refraction
-            ).unwrap(),
-            signal: Vector3::new(1.0, 1.0, 1.0)
+                0.0,
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, 0.0),
+                0.0,
+                0.0,
+            )
         }
     }
 
-    fn refracted_exit(&self, exited: Vector3<f64>, length: f64) -> BSDF {
-        let opacity = 1.0 - self.transparency;
-        let volume = f64::min(opacity * length * length, 1.0);
-        let tint = Vector3::new(1.0, 1.0, 1.0).lerp(&self.color, volume);
-        BSDF {
-            direction: exited,
-            signal: tint
+    // Stands in for a surface in the photograph that reflects specularly
+    // with the reflectance at normal incidence and gloss given.
+    pub fn shadow_catcher(reflectance: f64, gloss: f64) -> Self {
+        Self {
+            matte: Some(Matte::ShadowCatcher),
+            ..Self::new(
+                Vector3::new(1.0, 1.0, 1.0),
+                1.0,
+                0.0,
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::repeat(reflectance),
+                0.0,
+                gloss,
+            )
         }
     }
-}
 
-trait Pdf {
-    fn p(&self, v: Vector3<f64>) -> f64;
-    fn gen(&self) -> Vector3<f64>;
-}
+    pub fn matte(&self) -> Option<Matte> {
+        self.matte
+    }
 
-struct MixturePdf<'a> {
-    components: Vec<&'a dyn Pdf>,
-}
+    // an index matched boundary that only encloses a medium, e.g. smoke
+    pub fn volume(medium: Medium) -> Self {
+        Self::new(
+            Vector3::new(0.0, 0.0, 0.0),
+            1.0,
+            1.0,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            0.0,
+            0.0,
+        ).with_medium(medium)
+    }
 
-impl <'a> MixturePdf<'a> {
-    fn new(components: Vec<&'a dyn Pdf>) -> Self {
-        Self{components}
+    // Random walk subsurface scattering: a dielectric boundary around a dense
+    // scattering medium. The mean free path is per channel and the albedo is
+    // the multiple scattering colour seen on the surface.
+    pub fn subsurface(albedo: Vector3<f64>, mean_free_path: Vector3<f64>, refraction: f64) -> Self {
+        let reflectance = ((refraction - 1.0) / (refraction + 1.0)).powi(2);
+        let extinction = mean_free_path.map(|d| 1.0 / d.max(1e-6));
+        let scattering = albedo.map(single_scattering_albedo).component_mul(&extinction);
+        Self::new(
+            albedo,
+            refraction,
+            1.0,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::repeat(reflectance),
+            0.0,
+            0.0,
+        ).with_medium(Medium::new(extinction - scattering, scattering))
     }
-}
 
-impl <'a> Pdf for MixturePdf<'a> {
-    fn p(&self, v: Vector3<f64>) -> f64 {
-        let mut total = 0.0;
-        for component in self.components.iter() {
-            total += component.p(v);
+    // scales the emitted light over the surface, e.g. a picture on a screen
+    pub fn with_emission_texture(self, texture: Texture) -> Self {
+        Self {
+            emission_texture: Some(texture),
+            ..self
         }
-        total / self.components.len() as f64
     }
 
-    fn gen(&self) -> Vector3<f64> {
-        self.components.choose(&mut rand::thread_rng()).unwrap().gen()
+    // only emit from the side the normal points to
+    pub fn one_sided(self) -> Self {
+        Self {
+            one_sided: true,
+            ..self
+        }
     }
-}
 
+    // Narrows emission towards the normal by a power of the cosine to it,
+    // keeping the radiance along the normal the same.
+    pub fn with_falloff(self, power: f64) -> Self {
+        Self {
+            falloff: power.max(0.0),
+            ..self
+        }
+    }
 
-struct CosWeightedDiffuse {
-    u: f64,
-    v: f64,
-    normal: Vector3<f64>
-}
+    // names the buffer the sensor keeps light from this emitter in
+    pub fn with_light_group(self, name: &str) -> Self {
+        Self {
+            light_group: Some(name.to_string()),
+            ..self
+        }
+    }
 
-impl CosWeightedDiffuse {
-    fn new(normal: Vector3<f64>, u: f64, v: f64) -> Self {
-        Self{u, v, normal}
+    pub fn light_group(&self) -> Option<&str> {
+        self.light_group.as_deref()
     }
-}
 
-impl Pdf for CosWeightedDiffuse {
-    fn p(&self, v: Vector3<f64>) -> f64 {
-        let cosine = v.dot(&self.normal);
-        if cosine > 0.0 { cosine / std::f64::consts::PI } else { 0.0 }
+    pub fn can_emit(&self) -> bool {
+        self.light.norm() > 0.0
     }
 
-    fn gen(&self) -> Vector3<f64> {
-        let vec = Vector3::random_in_cos_hemisphere(self.u, self.v);
-        let onb = OrthonormalBasis::from_normal(self.normal);
-        onb.local(vec)
+    // luminous flux leaving each unit of area, ignoring any emission texture
+    pub fn exitance(&self) -> f64 {
+        let sides = if self.one_sided { 1.0 } else { 2.0 };
+        emission::luminance(&self.light) * sides * 2.0 * f64::consts::PI / (self.falloff + 2.0)
     }
-}
 
-struct LightWeightedDiffuse<'a> {
-    point: Point3<f64>,
-    normal: Vector3<f64>,
-    light: &'a Sphere
-}
+    // radiance leaving a point on the surface, with the cosine between the
+    // direction it leaves in and the normal
+    pub fn emit(&self, uv: Point2<f64>, cos_theta: f64) -> Vector3<f64> {
+        let cos_theta = if self.one_sided { cos_theta } else { cos_theta.abs() };
+        if cos_theta <= 0.0 || !self.can_emit() {
+            return Vector3::zeros();
+        }
 
-impl <'a> LightWeightedDiffuse<'a> {
-    fn new(point: Point3<f64>, normal: Vector3<f64>, light: &'a Sphere) -> Self {
-        Self{point, normal, light}
+        let light = match self.emission_texture {
+            Some(ref texture) => self.light.component_mul(&texture.value(uv)),
+            None => self.light
+        };
+        if self.falloff > 0.0 {
+            light * cos_theta.powf(self.falloff)
+        } else {
+            light
+        }
     }
-}
 
-impl <'a> Pdf for LightWeightedDiffuse<'a> {
-    fn p(&self, v: Vector3<f64>) -> f64 {
-        let light = self.light;
+    pub fn bsdf(&self, interaction: &SurfaceInteraction, u: f64, v: f64) -> BSDF {
+        if self.is_index_matched() {
+            return BSDF {
+                direction: -interaction.wo,
+                signal: Vector3::new(1.0, 1.0, 1.0),
+                pdf: None
+            };
+        }
 
-        // get bounding sphere center and radius
-        let center = light.center();
-        let radius = light.radius();
+        if let Some(ref bxdf) = self.bxdf {
+            return self.scattered(bxdf.as_ref(), interaction, u, v);
+        }
 
-        let cos_angle = v.dot(&self.normal);
-        if cos_angle <= 0.0 {
-            return 0.0;
+        let cos_incident = interaction.wo.dot(&interaction.surface.n);
+        if let (true, Some(conductor)) = (cos_incident > 0.0, self.conductor) {
+            let signal = match self.thin_film {
+                Some(ref film) => film.reflectance(
+                    cos_incident,
+                    interaction.exterior_index,
+                    &Substrate::Conductor(conductor),
+                    interaction.surface.uv,
+                    interaction.wavelength
+                ),
+                None => conductor.reflectance(cos_incident, interaction.exterior_index)
+            };
+            self.reflected(interaction, signal, u, v)
+        } else if cos_incident > 0.0 {
+            // brdf, with whatever isn't reflected carrying the complementary tint
+            let reflectance = self.fresnel(interaction);
+            let probability = reflectance.mean();
+            let transmitted = transmitted_tint(reflectance);
+
+            let mut test = FilteredProbabilityTest::new();
+            if test.or(probability) {
+                let signal = Vector3::new(1.0, 1.0, 1.0).lerp(&self.frensel, self.metal);
+                self.reflected(interaction, signal.component_mul(&(reflectance / probability)), u, v)
+            } else if test.or(self.transparency) {
+                self.refracted_entry(interaction).tinted(transmitted)
+            } else if test.or(self.metal) {
+                self.dead()
+            } else {
+                self.diffused(interaction, u, v).tinted(transmitted)
+            }
+        } else {
+            // btdf
+            let index = self.refraction.at(interaction.wavelength);
+            let exterior = interaction.exterior_index;
+            let reflectance = fresnel::dielectric(cos_incident, exterior, index);
+            match (-interaction.wo).refraction(&-interaction.surface.n, index, exterior) {
+                Some(exited) if rand::random::<f64>() >= reflectance => self.refracted_exit(exited),
+                _ => self.internally_reflected(interaction)
+            }
+        }
+    }
+
+    // The cosine weighted bsdf and the density bsdf() would sample a direction
+    // with, for the lobe it picks a density for. Only meaningful when that
+    // sample came with a density.
+    pub fn eval(&self, interaction: &SurfaceInteraction, wi: Vector3<f64>) -> (Vector3<f64>, f64) {
+        if let Some(ref bxdf) = self.bxdf {
+            let onb = OrthonormalBasis::from_normal_tangent(
+                interaction.surface.n,
+                interaction.surface.tangent
+            );
+            let (wo, wi) = (onb.to_local(interaction.wo), onb.to_local(wi));
+            return (bxdf.eval(&wo, &wi), bxdf.pdf(&wo, &wi));
+        }
+
+        let cos_outgoing = wi.dot(&interaction.surface.n);
+        if cos_outgoing <= 0.0 || interaction.wo.dot(&interaction.surface.n) <= 0.0 {
+            return (Vector3::zeros(), 0.0);
         }
 
-        // compute solid angle (hemisphere coverage)
-        let hyp = (center - self.point).norm();
-        let opp = radius;
-        let theta = (opp / hyp).asin();
-        let adj = opp / theta.tan();
-        let d = theta.cos() * adj;
-        let r = theta.sin() * adj;
+        let pdf = cos_outgoing / f64::consts::PI;
+        let tint = transmitted_tint(self.fresnel(interaction));
+        (self.color.component_mul(&tint) * pdf, pdf)
+    }
+
+    pub fn is_index_matched(&self) -> bool {
+        self.transparency >= 1.0 && self.refraction == Ior::Constant(1.0)
+    }
+
+    fn dead(&self) -> BSDF {
+        BSDF {
+            direction: Vector3::new(0.0, 0.0, 0.0),
+            signal: Vector3::new(0.0, 0.0, 0.0),
+            pdf: None
+        }
+    }
 
-        let coverage = if hyp < opp {
-            1.0
+    // Opaque dielectrics only describe their specular reflectance at normal
+    // incidence, so their index relative to the exterior is recovered from that.
+    fn fresnel(&self, interaction: &SurfaceInteraction) -> Vector3<f64> {
+        let exterior = interaction.exterior_index;
+        let index = if self.refraction == Ior::Constant(1.0) {
+            fresnel::index_from_reflectance(self.frensel.mean()) * exterior
         } else {
-            f64::min((r * r) / (d * d), 1.0)
+            self.refraction.at(interaction.wavelength)
         };
 
-        let a = (center - self.point).angle(&v);
+        let cos_incident = interaction.wo.dot(&interaction.surface.n);
+        match self.thin_film {
+            Some(ref film) => film.reflectance(
+                cos_incident,
+                exterior,
+                &Substrate::Dielectric(index),
+                interaction.surface.uv,
+                interaction.wavelength
+            ),
+            None => Vector3::repeat(fresnel::dielectric(cos_incident, exterior, index))
+        }
+    }
 
-        if a < radius {
-            coverage / std::f64::consts::PI
-        } else {
-            0.0
+    fn scattered(&self, bxdf: &dyn Bxdf, interaction: &SurfaceInteraction, u: f64, v: f64) -> BSDF {
+        let onb = OrthonormalBasis::from_normal_tangent(
+            interaction.surface.n,
+            interaction.surface.tangent
+        );
+        let wo = onb.to_local(interaction.wo);
+        let sampled = bxdf.sample(&wo, u, v).and_then(|wi| {
+            let pdf = bxdf.pdf(&wo, &wi);
+            if pdf > 0.0 {
+                Some(BSDF {
+                    direction: onb.local(wi),
+                    signal: bxdf.eval(&wo, &wi) / pdf,
+                    pdf: Some(pdf)
+                })
+            } else {
+                None
+            }
+        });
+
+        sampled.unwrap_or_else(|| self.dead())
+    }
+
+    fn diffused(&self, interaction: &SurfaceInteraction, u: f64, v: f64) -> BSDF {
+        let onb = OrthonormalBasis::from_normal(interaction.surface.n);
+        let direction = onb.local(Vector3::random_in_cos_hemisphere(u, v));
+        BSDF {
+            direction,
+            signal: self.color,
+            pdf: Some(direction.dot(&interaction.surface.n).max(0.0) / f64::consts::PI)
+        }
+    }
+
+    fn reflected(&self, interaction: &SurfaceInteraction, signal: Vector3<f64>, u: f64, v: f64) -> BSDF {
+        let mut reflected = -interaction.wo;
+        Reflection::new(Unit::new_normalize(interaction.surface.n), 0.0)
+            .reflect(&mut reflected);
+
+        BSDF{
+            direction: Vector3::random_in_cone(
+                &reflected,
+                1.0 - self.gloss,
+                u,
+                v
+            ),
+            signal,
+            pdf: None
         }
     }
 
-    fn gen(&self) -> Vector3<f64> {
-        let light = self.light;
+    fn refracted_entry(&self, interaction: &SurfaceInteraction) -> BSDF {
+        let entered = (-interaction.wo).refraction(
+            &interaction.surface.n,
+            interaction.exterior_index,
+            self.refraction.at(interaction.wavelength)
+        );
 
-        // get bounding sphere center and radius
-        let center = light.center();
-        let radius = light.radius();
+        match entered {
+            Some(direction) => BSDF {
+                direction,
+                signal: Vector3::new(1.0, 1.0, 1.0),
+                pdf: None
+            },
+            // entering a less dense material from a denser surrounding one
+            None => self.internally_reflected(interaction)
+        }
+    }
 
-        // get random point in disk
-        let point = loop {
-            let x = rand::random::<f64>() * 2.0 - 1.0;
-            let y = rand::random::<f64>() * 2.0 - 1.0;
-            if x*x + y*y <= 1.0 {
-                let l = (center - self.point).normalize();
-                let u = l.cross(&Vector3::random_in_sphere()).normalize();
-                let v = l.cross(&u);
+    fn internally_reflected(&self, interaction: &SurfaceInteraction) -> BSDF {
+        let mut reflected = -interaction.wo;
+        Reflection::new(Unit::new_normalize(interaction.surface.n), 0.0)
+            .reflect(&mut reflected);
 
-                break center + (u * x * radius) + (v * y * radius);
-            }
-        };
+        BSDF {
+            direction: reflected,
+            signal: Vector3::new(1.0, 1.0, 1.0),
+            pdf: None
+        }
+    }
+
+    fn refracted_exit(&self, exited: Vector3<f64>) -> BSDF {
+        // absorption along the path inside is handled by the interior medium
+        BSDF {
+            direction: exited,
+            signal: Vector3::new(1.0, 1.0, 1.0),
+            pdf: None
+        }
+    }
+}
 
-        // construct ray toward light point
-        (point - self.point).normalize()
+// what is left of white light after a reflectance, rescaled so the light
+// that's left over keeps its energy
+fn transmitted_tint(reflectance: Vector3<f64>) -> Vector3<f64> {
+    let probability = reflectance.mean();
+    if probability < 1.0 {
+        (Vector3::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - probability)
+    } else {
+        Vector3::new(1.0, 1.0, 1.0)
     }
 }
 
+// Inverts the surface albedo produced by a random walk in a semi-infinite
+// medium (Chiang et al. 2016), so the albedo input matches what is rendered.
+fn single_scattering_albedo(albedo: f64) -> f64 {
+    let a = albedo.clamp(0.0, 1.0);
+    1.0 - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+}
+
 struct FilteredProbabilityTest {
     r: f64,
     p: f64
@@ -309,8 +587,9 @@ impl FilteredProbabilityTest {
         Self{r: rand::random::<f64>(), p: 0.0}
     }
 
+    // each test only sees the probability left over by the ones before it
     fn or(&mut self, p: f64) -> bool {
-        self.p = (1.0 - self.p) * p;
+        self.p += (1.0 - self.p) * p;
         self.r <= self.p
     }
 }
@@ -321,19 +600,54 @@ mod test {
     use nalgebra::Vector3;
 
     #[test]
-    fn schilck_is_correct() {
+    fn emission_follows_sides_falloff_and_texture() {
+        let uv = Point2::new(0.5, 0.5);
+        let bulb = Material::emitter(Vector3::new(2.0, 2.0, 2.0));
+        assert_eq!(bulb.emit(uv, -0.3), Vector3::new(2.0, 2.0, 2.0));
+
+        let panel = bulb.clone().one_sided().with_falloff(2.0);
+        assert_eq!(panel.emit(uv, -0.3), Vector3::zeros());
+        assert!((panel.emit(uv, 0.5).x - 0.5).abs() < 1e-12);
+        assert_eq!(panel.emit(uv, 1.0), Vector3::new(2.0, 2.0, 2.0));
+
+        let screen = bulb.with_emission_texture(Texture::Constant(Vector3::new(1.0, 0.5, 0.0)));
+        assert_eq!(screen.emit(uv, 0.7), Vector3::new(2.0, 1.0, 0.0));
+    }
 
-        let incident = Vector3::new(
-            0.9999877074290066,
-            0.002070457097031252,
-            0.004505352182583419,
-        );
+    #[test]
+    fn lobes_are_picked_from_the_probability_left_over() {
+        let n = 1000;
+        let mut counts = [0; 3];
+        for i in 0..n {
+            let mut test = FilteredProbabilityTest{r: (i as f64 + 0.5) / n as f64, p: 0.0};
+            let lobe = if test.or(0.5) { 0 } else if test.or(0.5) { 1 } else { 2 };
+            counts[lobe] += 1;
+        }
+        assert_eq!(counts, [500, 250, 250]);
+    }
+
+    #[test]
+    fn single_scattering_albedo_inverts_surface_albedo() {
+        // the diffuse reflectance of a semi-infinite isotropic medium (van de
+        // Hulst), which the inversion is fitted to
+        let surface_albedo = |single: f64| {
+            let s = (1.0 - single).sqrt();
+            (1.0 - s) * (1.0 - 0.139 * s) / (1.0 + 1.17 * s)
+        };
+        for i in 0..=10 {
+            let albedo = i as f64 / 10.0;
+            assert!((surface_albedo(single_scattering_albedo(albedo)) - albedo).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn fresnel_recovers_index_from_specular_reflectance() {
         let normal = Vector3::new(
             -0.42430229364657923,
             0.17526903761586785,
             -0.8883964925974548,
         );
-        
+
         let material = Material::new(
             Vector3::new(0.1, 0.1, 1.0),
             1.0,
@@ -344,13 +658,54 @@ mod test {
             0.2
         );
 
-        assert_eq!(
-            material.schilck(&incident, &normal),
-            Vector3::new(
-                0.09881546766725074,
-                0.09881546766725074,
-                0.09881546766725074
-            )
-        )
+        let interaction = SurfaceInteraction {
+            wo: normal,
+            surface: SurfacePoint {
+                n: normal,
+                p: Point3::new(0.0, 0.0, 0.0),
+                uv: Point2::new(0.0, 0.0),
+                tangent: Vector3::new(1.0, 0.0, 0.0)
+            },
+            wavelength: None,
+            exterior_index: 1.0
+        };
+
+        assert!((material.fresnel(&interaction).x - 0.04).abs() < 1e-12);
+    }
+
+    #[test]
+    fn eval_agrees_with_sampled_diffuse_directions() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let material = Material::new(
+            Vector3::new(0.8, 0.4, 0.2),
+            1.0,
+            0.0,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            0.0,
+            0.0
+        );
+
+        let interaction = SurfaceInteraction {
+            wo: Vector3::new(0.6, 0.8, 0.0),
+            surface: SurfacePoint {
+                n: normal,
+                p: Point3::new(0.0, 0.0, 0.0),
+                uv: Point2::new(0.0, 0.0),
+                tangent: Vector3::new(1.0, 0.0, 0.0)
+            },
+            wavelength: None,
+            exterior_index: 1.0
+        };
+
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)].iter() {
+            let sample = material.bsdf(&interaction, *u, *v);
+            let (scattered, pdf) = material.eval(&interaction, sample.direction);
+            assert!((sample.pdf.unwrap() - pdf).abs() < 1e-9);
+            assert!((scattered / pdf - sample.signal).norm() < 1e-9);
+        }
+
+        let (below, _) = material.eval(&interaction, -normal);
+        assert_eq!(below, Vector3::zeros());
     }
 }
@@ -0,0 +1,297 @@
+use crate::bxdf::OrenNayar;
+use crate::emission;
+use crate::fresnel::Conductor;
+use crate::material::Material;
+use crate::medium::Medium;
+use crate::spectrum::Ior;
+use crate::texture::Texture;
+use nalgebra::Vector3;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+const PRESETS: &str = include_str!("material_presets.txt");
+
+// Materials that scenes refer to by name. The file format is described at the
+// top of material_presets.txt.
+pub struct MaterialLibrary {
+    materials: HashMap<String, Material>,
+}
+
+impl MaterialLibrary {
+    pub fn presets() -> Self {
+        Self::parse(PRESETS).expect("material presets should parse")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut materials = HashMap::new();
+        let mut current: Option<Definition> = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let content = line.split('#').next().unwrap_or("");
+            let fields = content.split_whitespace().collect::<Vec<_>>();
+            if fields.is_empty() {
+                continue;
+            }
+
+            if content.starts_with(char::is_whitespace) {
+                let definition = current
+                    .as_mut()
+                    .ok_or_else(|| invalid(line_number, "property outside of a material"))?;
+                if definition.properties.insert(fields[0], (line_number, fields[1..].to_vec())).is_some() {
+                    return Err(invalid(line_number, &format!("{} is set twice", fields[0])));
+                }
+            } else {
+                if let Some(definition) = current.take() {
+                    materials.insert(definition.name.to_string(), definition.build()?);
+                }
+                if fields.len() != 2 {
+                    return Err(invalid(line_number, "expected a material name and kind"));
+                }
+                if materials.contains_key(fields[0]) {
+                    return Err(invalid(line_number, &format!("{} is defined twice", fields[0])));
+                }
+                current = Some(Definition {
+                    name: fields[0],
+                    kind: fields[1],
+                    line_number,
+                    properties: HashMap::new(),
+                });
+            }
+        }
+
+        if let Some(definition) = current {
+            materials.insert(definition.name.to_string(), definition.build()?);
+        }
+
+        Ok(Self { materials })
+    }
+
+    // materials in the other library replace any with the same name
+    pub fn extend(&mut self, other: MaterialLibrary) {
+        self.materials.extend(other.materials);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Material> {
+        self.materials.get(name).cloned()
+    }
+}
+
+fn invalid(line_number: usize, message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_number, message))
+}
+
+struct Definition<'a> {
+    name: &'a str,
+    kind: &'a str,
+    line_number: usize,
+    properties: HashMap<&'a str, (usize, Vec<&'a str>)>,
+}
+
+impl<'a> Definition<'a> {
+    fn build(&self) -> io::Result<Material> {
+        let allowed: &[&str] = match self.kind {
+            "diffuse" => &["color", "specular", "gloss"],
+            "rough" => &["color", "roughness"],
+            "dielectric" => &["ior", "cauchy", "sellmeier", "absorption", "scattering", "anisotropy"],
+            "metal" => &["conductor", "eta", "k", "gloss"],
+            "subsurface" => &["albedo", "mfp", "ior"],
+            "emitter" => &["light", "temperature", "nits", "sides", "falloff", "group"],
+            kind => return Err(invalid(self.line_number, &format!("unknown material kind {}", kind))),
+        };
+        for (property, (line_number, _)) in self.properties.iter() {
+            if !allowed.contains(property) && *property != "priority" && *property != "opacity" {
+                return Err(invalid(*line_number, &format!("{} materials have no {}", self.kind, property)));
+            }
+        }
+
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let material = match self.kind {
+            "diffuse" => Material::new(
+                self.color("color", Vector3::repeat(0.8))?,
+                1.0,
+                0.0,
+                zero,
+                self.color("specular", Vector3::repeat(0.04))?,
+                0.0,
+                self.scalar("gloss", 0.0)?,
+            ),
+            "rough" => Material::from_bxdf(Arc::new(OrenNayar::new(
+                self.color("color", Vector3::repeat(0.8))?,
+                self.scalar("roughness", 0.5)?,
+            ))),
+            "dielectric" => {
+                let glass = Material::new(zero, 1.0, 1.0, zero, Vector3::repeat(0.04), 0.0, 0.0)
+                    .with_dispersion(self.ior()?);
+                if self.properties.contains_key("absorption") || self.properties.contains_key("scattering") {
+                    glass.with_medium(
+                        Medium::new(self.color("absorption", zero)?, self.color("scattering", zero)?)
+                            .with_anisotropy(self.scalar("anisotropy", 0.0)?),
+                    )
+                } else {
+                    glass
+                }
+            }
+            "metal" => Material::metal(self.conductor()?, self.scalar("gloss", 1.0)?),
+            "subsurface" => Material::subsurface(
+                self.color("albedo", Vector3::repeat(0.8))?,
+                self.color("mfp", Vector3::repeat(0.1))?,
+                self.scalar("ior", 1.4)?,
+            ),
+            _ => {
+                let light = if self.properties.contains_key("light") {
+                    self.color("light", zero)?
+                } else {
+                    emission::nits(self.scalar("temperature", 6500.0)?, self.scalar("nits", 100.0)?)
+                };
+                let emitter = Material::emitter(light).with_falloff(self.scalar("falloff", 0.0)?);
+                let emitter = match self.properties.get("group") {
+                    Some((_, names)) if names.len() == 1 => emitter.with_light_group(names[0]),
+                    Some((line_number, _)) => return Err(invalid(*line_number, "group takes one name")),
+                    None => emitter,
+                };
+                match self.numbers("sides")? {
+                    None => emitter,
+                    Some((_, ref numbers)) if numbers[..] == [1.0] => emitter.one_sided(),
+                    Some((_, ref numbers)) if numbers[..] == [2.0] => emitter,
+                    Some((line_number, _)) => return Err(invalid(line_number, "emitters have one or two sides")),
+                }
+            }
+        };
+
+        let material = if self.properties.contains_key("opacity") {
+            material.with_opacity(Texture::scalar(self.scalar("opacity", 1.0)?))
+        } else {
+            material
+        };
+        Ok(material.with_priority(self.priority()?))
+    }
+
+    fn priority(&self) -> io::Result<u32> {
+        match self.numbers("priority")? {
+            None => Ok(0),
+            Some((_, ref numbers)) if numbers.len() == 1
+                && numbers[0] >= 0.0
+                && numbers[0] <= f64::from(u32::MAX)
+                && numbers[0].fract() == 0.0 => Ok(numbers[0] as u32),
+            Some((line_number, _)) => Err(invalid(line_number, "priority takes one whole number of at least zero")),
+        }
+    }
+
+    fn numbers(&self, property: &str) -> io::Result<Option<(usize, Vec<f64>)>> {
+        match self.properties.get(property) {
+            None => Ok(None),
+            Some((line_number, values)) => values
+                .iter()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map(|numbers| Some((*line_number, numbers)))
+                .map_err(|_| invalid(*line_number, &format!("{} should be numeric", property))),
+        }
+    }
+
+    fn scalar(&self, property: &str, default: f64) -> io::Result<f64> {
+        match self.numbers(property)? {
+            None => Ok(default),
+            Some((_, ref numbers)) if numbers.len() == 1 => Ok(numbers[0]),
+            Some((line_number, _)) => Err(invalid(line_number, &format!("{} takes one value", property))),
+        }
+    }
+
+    fn color(&self, property: &str, default: Vector3<f64>) -> io::Result<Vector3<f64>> {
+        match self.numbers(property)? {
+            None => Ok(default),
+            Some((_, ref numbers)) if numbers.len() == 1 => Ok(Vector3::repeat(numbers[0])),
+            Some((_, ref numbers)) if numbers.len() == 3 => Ok(Vector3::from_column_slice(numbers)),
+            Some((line_number, _)) => Err(invalid(line_number, &format!("{} takes one or three values", property))),
+        }
+    }
+
+    fn ior(&self) -> io::Result<Ior> {
+        if let Some((line_number, numbers)) = self.numbers("cauchy")? {
+            return match numbers[..] {
+                [a, b] => Ok(Ior::Cauchy { a, b }),
+                _ => Err(invalid(line_number, "cauchy takes two coefficients")),
+            };
+        }
+
+        if let Some((line_number, numbers)) = self.numbers("sellmeier")? {
+            return match numbers[..] {
+                [b1, b2, b3, c1, c2, c3] => Ok(Ior::Sellmeier { b: [b1, b2, b3], c: [c1, c2, c3] }),
+                _ => Err(invalid(line_number, "sellmeier takes six coefficients")),
+            };
+        }
+
+        Ok(Ior::Constant(self.scalar("ior", 1.5)?))
+    }
+
+    fn conductor(&self) -> io::Result<Conductor> {
+        match self.properties.get("conductor") {
+            Some((line_number, names)) => names
+                .first()
+                .and_then(|name| Conductor::named(name))
+                .ok_or_else(|| invalid(*line_number, "unknown conductor")),
+            None => Ok(Conductor::new(
+                self.color("eta", Vector3::repeat(1.5))?,
+                self.color("k", Vector3::repeat(3.0))?,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn presets_parse() {
+        let presets = MaterialLibrary::presets();
+        for name in ["glass", "water", "diamond", "gold", "copper", "rubber", "clay"].iter() {
+            assert!(presets.get(name).is_some(), "missing preset {}", name);
+        }
+        assert!(presets.get("diamond").unwrap().is_dispersive());
+        assert_eq!(presets.get("ice").unwrap().priority(), 2);
+    }
+
+    #[test]
+    fn files_override_presets() {
+        let mut library = MaterialLibrary::presets();
+        library.extend(MaterialLibrary::parse("glass dielectric\n    ior 1.9 # dense flint\n").unwrap());
+        assert_eq!(library.get("glass").unwrap().interior_index(None), 1.9);
+    }
+
+    #[test]
+    fn reports_the_offending_line() {
+        let error = MaterialLibrary::parse("paint diffuse\n    color 1 0\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 2: color takes one or three values");
+
+        let error = MaterialLibrary::parse("lamp emitter\n    group key fill\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 2: group takes one name");
+
+        let error = MaterialLibrary::parse("lamp emitter\n    nits 500\n    sides 1.9\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 3: emitters have one or two sides");
+
+        let error = MaterialLibrary::parse("paint diffuse\nlamp emitter\npaint rough\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 3: paint is defined twice");
+
+        for priority in ["-3", "2.7", "1 2"].iter() {
+            let text = format!("glass dielectric\n    ior 1.5\n    priority {}\n", priority);
+            let error = MaterialLibrary::parse(&text).err().unwrap();
+            assert_eq!(error.to_string(), "line 3: priority takes one whole number of at least zero");
+        }
+        assert!(MaterialLibrary::parse("glass dielectric\n    priority 2\n").is_ok());
+
+        let error = MaterialLibrary::parse("paint diffuse\n    ior 1.5\n").err().unwrap();
+        assert_eq!(error.to_string(), "line 2: diffuse materials have no ior");
+
+        assert!(MaterialLibrary::parse("    color 1\n").is_err());
+        assert!(MaterialLibrary::parse("paint lacquer\n").is_err());
+    }
+}
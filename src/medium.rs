@@ -0,0 +1,305 @@
+use crate::onb::OrthonormalBasis;
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+use crate::volume::VoxelGrid;
+use nalgebra::Vector3;
+use std::f64;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Medium {
+    absorption: Vector3<f64>,
+    scattering: Vector3<f64>,
+    density: Option<Arc<VoxelGrid>>,
+    phase: HenyeyGreenstein,
+}
+
+pub enum MediumSample {
+    Scattered { distance: f64, weight: Vector3<f64> },
+    Transmitted { weight: Vector3<f64> },
+    Absorbed,
+}
+
+impl Medium {
+    pub fn new(absorption: Vector3<f64>, scattering: Vector3<f64>) -> Self {
+        Self {
+            absorption,
+            scattering,
+            density: None,
+            phase: HenyeyGreenstein::new(0.0),
+        }
+    }
+
+    // scales the coefficients by a grid stretched over the bounding object
+    pub fn with_density(self, grid: Arc<VoxelGrid>) -> Self {
+        Self {
+            density: Some(grid),
+            ..self
+        }
+    }
+
+    pub fn with_anisotropy(self, g: f64) -> Self {
+        Self {
+            phase: HenyeyGreenstein::new(g),
+            ..self
+        }
+    }
+
+    pub fn phase(&self) -> &HenyeyGreenstein {
+        &self.phase
+    }
+
+    fn extinction(&self) -> Vector3<f64> {
+        self.absorption + self.scattering
+    }
+
+    pub fn transmittance(&self, distance: f64) -> Vector3<f64> {
+        self.extinction().map(|sigma| (-sigma * distance).exp())
+    }
+
+    pub fn sample(&self, ray: &Ray, max_distance: f64, bounds: &Sphere) -> MediumSample {
+        match self.density {
+            None => self.sample_homogeneous(max_distance, rand::random(), rand::random()),
+            Some(ref grid) => self.sample_heterogeneous(grid, ray, max_distance, bounds),
+        }
+    }
+
+    // Fraction of light getting through the medium along a ray for a finite
+    // distance, estimated by ratio tracking where a density grid makes it
+    // vary: tentative collisions against the majorant each scale it by the
+    // chance of being a null collision.
+    pub fn transmittance_along(&self, ray: &Ray, distance: f64, bounds: &Sphere) -> Vector3<f64> {
+        let grid = match self.density {
+            None => return self.transmittance(distance),
+            Some(ref grid) => grid,
+        };
+
+        let extinction = self.extinction();
+        let majorant = grid.max_density() * extinction.max();
+        let mut transmittance = Vector3::new(1.0, 1.0, 1.0);
+        if majorant <= 0.0 {
+            return transmittance;
+        }
+
+        let mut travelled = 0.0;
+        loop {
+            travelled -= (1.0 - rand::random::<f64>()).ln() / majorant;
+            if travelled >= distance {
+                return transmittance;
+            }
+
+            let density = grid.density(bounds.local(ray.origin + ray.direction * travelled));
+            let null = Vector3::repeat(1.0) - extinction * (density / majorant);
+            transmittance = transmittance.component_mul(&null);
+        }
+    }
+
+    // Distances are sampled from the extinction of a randomly chosen channel
+    // and weighted by the pdf averaged over all three, so a medium that only
+    // absorbs one channel doesn't produce fireflies in the others.
+    fn sample_homogeneous(&self, max_distance: f64, channel: f64, u: f64) -> MediumSample {
+        let extinction = self.extinction();
+        let sigma = extinction[((channel * 3.0) as usize).min(2)];
+        let distance = if sigma > 0.0 {
+            -(1.0 - u).ln() / sigma
+        } else {
+            f64::INFINITY
+        };
+
+        let transmittance = self.transmittance(distance.min(max_distance));
+        if distance < max_distance {
+            let pdf = extinction.component_mul(&transmittance).mean();
+            MediumSample::Scattered {
+                distance,
+                weight: self.scattering.component_mul(&transmittance) / pdf,
+            }
+        } else if transmittance.mean() > 0.0 {
+            MediumSample::Transmitted {
+                weight: transmittance / transmittance.mean(),
+            }
+        } else {
+            MediumSample::Transmitted {
+                weight: Vector3::zeros(),
+            }
+        }
+    }
+
+    // Delta tracking against a majorant for the densest voxel. Tentative
+    // collisions are classified as absorption, scattering or null using the
+    // channel averaged coefficients, with the throughput carrying the
+    // per-channel correction.
+    fn sample_heterogeneous(
+        &self,
+        grid: &VoxelGrid,
+        ray: &Ray,
+        max_distance: f64,
+        bounds: &Sphere,
+    ) -> MediumSample {
+        let majorant = grid.max_density() * self.extinction().max();
+        let mut weight = Vector3::new(1.0, 1.0, 1.0);
+        if majorant <= 0.0 {
+            return MediumSample::Transmitted { weight };
+        }
+
+        let mut distance = 0.0;
+        loop {
+            distance -= (1.0 - rand::random::<f64>()).ln() / majorant;
+            if distance >= max_distance {
+                return MediumSample::Transmitted { weight };
+            }
+
+            let density = grid.density(bounds.local(ray.origin + ray.direction * distance));
+            let absorption = self.absorption * density;
+            let scattering = self.scattering * density;
+            let null = Vector3::repeat(majorant) - absorption - scattering;
+
+            let p_absorb = absorption.mean() / majorant;
+            let p_scatter = scattering.mean() / majorant;
+            let r = rand::random::<f64>();
+            if r < p_absorb {
+                return MediumSample::Absorbed;
+            } else if r < p_absorb + p_scatter {
+                weight = weight.component_mul(&(scattering / (majorant * p_scatter)));
+                return MediumSample::Scattered { distance, weight };
+            } else {
+                weight = weight.component_mul(&(null / (majorant * (1.0 - p_absorb - p_scatter))));
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self {
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    // samples a new direction of travel relative to the incoming one
+    pub fn sample(&self, direction: &Vector3<f64>, u: f64, v: f64) -> Vector3<f64> {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        };
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f64::consts::PI * v;
+        OrthonormalBasis::from_normal(*direction).local(Vector3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+
+    // the phase function for turning from one direction of travel to another,
+    // which is also the solid angle density sample picks it with
+    pub fn pdf(&self, direction: &Vector3<f64>, scattered: &Vector3<f64>) -> f64 {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * direction.dot(scattered);
+        (1.0 - g * g) / (4.0 * f64::consts::PI * denominator * denominator.sqrt())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::material::Material;
+    use nalgebra::Point3;
+
+    #[test]
+    fn transmittance_follows_beer_lambert() {
+        let medium = Medium::new(Vector3::new(0.5, 1.0, 2.0), Vector3::new(0.5, 0.0, 0.0));
+        let transmittance = medium.transmittance(2.0);
+        assert!((transmittance.x - (-2.0f64).exp()).abs() < 1e-12);
+        assert!((transmittance.y - (-2.0f64).exp()).abs() < 1e-12);
+        assert!((transmittance.z - (-4.0f64).exp()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sampling_is_unbiased_for_transmittance() {
+        let medium = Medium::new(Vector3::new(0.2, 1.0, 3.0), Vector3::new(0.0, 0.0, 0.0));
+        let n = 200_000;
+        let mut total = Vector3::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            if let MediumSample::Transmitted { weight } =
+                medium.sample_homogeneous(1.0, rand::random(), rand::random())
+            {
+                total += weight;
+            }
+        }
+
+        let estimate = total / n as f64;
+        let expected = medium.transmittance(1.0);
+        for c in 0..3 {
+            assert!((estimate[c] - expected[c]).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn ratio_tracking_matches_beer_lambert_in_a_uniform_grid() {
+        let grid = Arc::new(VoxelGrid::new(2, 2, 2, vec![1.0; 8]));
+        let medium = Medium::new(Vector3::new(0.2, 1.0, 3.0), Vector3::new(0.5, 0.5, 0.0)).with_density(grid);
+        let bounds = Sphere::new(0, Point3::new(0.0, 0.0, 0.0), 1.0, Material::volume(medium.clone()));
+        let ray = Ray { origin: Point3::new(-0.5, 0.0, 0.0), direction: Vector3::new(1.0, 0.0, 0.0) };
+
+        let n = 20_000;
+        let mut total = Vector3::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            total += medium.transmittance_along(&ray, 0.8, &bounds);
+        }
+
+        let estimate = total / n as f64;
+        let expected = medium.transmittance(0.8);
+        for c in 0..3 {
+            assert!((estimate[c] - expected[c]).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_mean_cosine_is_anisotropy() {
+        let direction = Vector3::new(0.0, 0.6, 0.8);
+        for g in [-0.7, 0.0, 0.3, 0.9].iter() {
+            let phase = HenyeyGreenstein::new(*g);
+            let n = 100_000;
+            let mut total = 0.0;
+            for _ in 0..n {
+                total += phase
+                    .sample(&direction, rand::random(), rand::random())
+                    .dot(&direction);
+            }
+            assert!((total / n as f64 - g).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_pdf_matches_its_samples() {
+        let direction = Vector3::new(0.0, 0.6, 0.8);
+        let phase = HenyeyGreenstein::new(0.6);
+        let (n, bins) = (200_000, 8);
+        let mut counts = vec![0; bins];
+        for _ in 0..n {
+            let cos_theta = phase.sample(&direction, rand::random(), rand::random()).dot(&direction);
+            counts[(((cos_theta + 1.0) / 2.0 * bins as f64) as usize).min(bins - 1)] += 1;
+        }
+
+        // the chance of landing in each band of cosines, integrated over it
+        let steps = 1000;
+        for (bin, count) in counts.iter().enumerate() {
+            let chance = (0..steps).map(|i| {
+                let cos_theta = -1.0 + 2.0 * (bin as f64 + (i as f64 + 0.5) / steps as f64) / bins as f64;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let scattered = OrthonormalBasis::from_normal(direction).local(Vector3::new(sin_theta, 0.0, cos_theta));
+                phase.pdf(&direction, &scattered) * 2.0 * f64::consts::PI * 2.0 / (bins * steps) as f64
+            }).sum::<f64>();
+            assert!((*count as f64 / n as f64 - chance).abs() < 0.005);
+        }
+    }
+}
@@ -5,7 +5,7 @@ pub struct OrthonormalBasis(Vector3<f64>, Vector3<f64>, Vector3<f64>);
 impl OrthonormalBasis {
     pub fn from_normal(n: Vector3<f64>) -> Self {
         let w = n.normalize();
-        let w_orth = if n.x > 0.7 {
+        let w_orth = if n.x.abs() > 0.7 {
             Vector3::new(0.0, 1.0, 0.0)
         } else {
             Vector3::new(1.0, 0.0, 0.0)
@@ -17,6 +17,18 @@ impl OrthonormalBasis {
         Self(u, v, w)
     }
 
+    // a basis whose first axis follows the surface tangent
+    pub fn from_normal_tangent(n: Vector3<f64>, tangent: Vector3<f64>) -> Self {
+        let w = n.normalize();
+        let u = tangent - w * w.dot(&tangent);
+        if u.norm_squared() < 1e-12 {
+            return Self::from_normal(n);
+        }
+
+        let u = u.normalize();
+        Self(u, w.cross(&u), w)
+    }
+
     fn u(&self) -> Vector3<f64> { self.0 }
     fn v(&self) -> Vector3<f64> { self.1 }
     fn w(&self) -> Vector3<f64> { self.2 }
@@ -24,4 +36,9 @@ impl OrthonormalBasis {
     pub fn local(&self, a: Vector3<f64>) -> Vector3<f64> {
         a.x * self.u() + a.y * self.v() + a.z * self.w()
     }
+
+    // the inverse of `local`, taking a world direction into this basis
+    pub fn to_local(&self, a: Vector3<f64>) -> Vector3<f64> {
+        Vector3::new(a.dot(&self.u()), a.dot(&self.v()), a.dot(&self.w()))
+    }
 }
\ No newline at end of file
@@ -13,7 +13,6 @@ pub trait DirectionExt {
     fn random_in_sphere() -> Self;
     fn random_in_cos_hemisphere(u: f64, v: f64) -> Self;
     fn random_in_cone(direction: &Self, width: f64, u: f64, v: f64) -> Self;
-    fn component_average(&self) -> f64;
     fn greyscale(&self) -> Self;
     fn refraction(
         &self,
@@ -38,8 +37,8 @@ impl DirectionExt for Vector3<f64> {
     fn random_in_cos_hemisphere(u: f64, v: f64) -> Self {
         let phi = 2.0 * std::f64::consts::PI * u;
         Vector3::new(
-            phi.cos() * 2.0 * v.sqrt(),
-            phi.sin() * 2.0 * v.sqrt(),
+            phi.cos() * v.sqrt(),
+            phi.sin() * v.sqrt(),
             (1.0 - v).sqrt()
         )
     }
@@ -61,10 +60,6 @@ impl DirectionExt for Vector3<f64> {
         d.normalize()
     }
 
-    fn component_average(&self) -> f64 {
-        (self.x + self.y + self.z) / 3.0
-    }
-
     fn greyscale(&self) -> Self {
         Self::new(self.mean(), self.mean(), self.mean())
     }
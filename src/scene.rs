@@ -1,13 +1,21 @@
+use crate::background::Background;
 use crate::camera::Camera;
+use crate::fresnel;
+use crate::light::{Light, LightSample};
+use crate::light_sampler::{Extent, LightSampler};
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::sphere::Sphere;
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Point2, Point3, Vector3};
+use std::collections::HashMap;
+use std::sync::OnceLock;
 
 #[derive(Copy, Clone)]
 pub struct Intersection<'a> {
     pub hit: Point3<f64>,
     pub normal: Vector3<f64>,
+    pub uv: Point2<f64>,
+    pub tangent: Vector3<f64>,
     pub material: &'a Material,
     pub object: &'a Sphere,
     pub distance: f64,
@@ -21,24 +29,106 @@ struct Hit<'a> {
 pub struct Scene {
     pub camera: Camera,
     objects: Vec<Sphere>,
-    lights: Vec<usize>
+    emitters: Vec<usize>,
+    lights: Vec<(Light, Option<String>)>,
+    // built from the lights when first needed, so that a scene assembled
+    // one light at a time builds it only once
+    light_index: OnceLock<LightIndex>,
+    // what the camera sees beyond the objects in place of the lights there
+    camera_background: Option<Background>
+}
+
+struct LightIndex {
+    // emitters come before other lights in the sampler, and are found there
+    // by their object's index
+    sampler: LightSampler,
+    emitter_lights: HashMap<usize, usize>,
+    // Names of the groups the sensor splits light into, the first holding
+    // any light not given a group, and the group of each light.
+    light_groups: Vec<String>,
+    groups: Vec<usize>,
 }
 
 impl Scene {
     pub fn new(objects: Vec<Sphere>, camera: Camera) -> Scene {
-        let lights = objects.iter().enumerate().filter(|(i, object)| {
+        let emitters = objects.iter().enumerate().filter(|(i, object)| {
             object.material().can_emit()
         }).map(|(i, _)| i).collect::<Vec<_>>();
 
-        Scene { objects, camera, lights }
+        Scene {
+            objects,
+            camera,
+            emitters,
+            lights: Vec::new(),
+            light_index: OnceLock::new(),
+            camera_background: None
+        }
     }
 
     pub fn add_object(&mut self, object: Sphere, is_light: bool) {
         if is_light {
-            self.lights.push(self.objects.len());
+            self.emitters.push(self.objects.len());
         }
 
         self.objects.push(object);
+        self.light_index = OnceLock::new();
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push((light, None));
+        self.light_index = OnceLock::new();
+    }
+
+    pub fn add_grouped_light(&mut self, light: Light, group: &str) {
+        self.lights.push((light, Some(group.to_string())));
+        self.light_index = OnceLock::new();
+    }
+
+    // Shows the camera a background of its own where it looks past the
+    // objects, rather than the environment lighting the scene. Reflections
+    // and refractions still see the lighting.
+    pub fn set_camera_background(&mut self, background: Background) {
+        self.camera_background = Some(background);
+    }
+
+    pub fn light_groups(&self) -> &[String] {
+        &self.light_index().light_groups
+    }
+
+    fn light_index(&self) -> &LightIndex {
+        self.light_index.get_or_init(|| self.index_lights())
+    }
+
+    fn index_lights(&self) -> LightIndex {
+        let emitters = self.emitters.iter().map(|i| {
+            let emitter = &self.objects[*i];
+            let radius = Vector3::repeat(emitter.radius());
+            Extent::Local {
+                min: emitter.center() - radius,
+                max: emitter.center() + radius,
+                power: emitter.material().exitance() * emitter.area()
+            }
+        });
+        let extents = emitters.chain(self.lights.iter().map(|(light, _)| light.extent())).collect::<Vec<_>>();
+
+        let sampler = LightSampler::new(&extents);
+        let emitter_lights = self.emitters.iter().enumerate().map(|(light, i)| {
+            (self.objects[*i].index(), light)
+        }).collect();
+
+        let names = self.emitters.iter()
+            .map(|i| self.objects[*i].material().light_group())
+            .chain(self.lights.iter().map(|(_, group)| group.as_deref()))
+            .collect::<Vec<_>>();
+        let mut light_groups = vec!["default".to_string()];
+        let groups = names.iter().map(|name| match name {
+            Some(name) => light_groups.iter().position(|group| group == name).unwrap_or_else(|| {
+                light_groups.push(name.to_string());
+                light_groups.len() - 1
+            }),
+            None => 0
+        }).collect();
+        LightIndex { sampler, emitter_lights, light_groups, groups }
     }
 
     pub fn intersect(&self, ray: &Ray) -> Option<Intersection> {
@@ -49,12 +139,14 @@ impl Scene {
                 Some(ref hit) if distance < hit.distance => Some(Hit{object, distance}),
                 c => c
             }
-        }).map(|hit| {
+        }).filter(|hit| hit.distance.is_finite()).map(|hit| {
             let point = ray.origin + (ray.direction * hit.distance);
             let normal = (point - hit.object.center()).normalize();
             Intersection {
                 hit: point,
                 normal,
+                uv: hit.object.uv(&normal),
+                tangent: hit.object.tangent(&normal),
                 material: hit.object.material(),
                 distance: hit.distance,
                 object: hit.object
@@ -62,24 +154,144 @@ impl Scene {
         })
     }
 
-    pub fn bg(&self, ray: &Ray) -> Vector3<f64> {
-        Vector3::new(1.0, 0.0, 0.0)
+    // Fraction of light that gets from one point to another through cutout
+    // surfaces, surfaces that don't bend light, such as emitters and the
+    // boundaries of smoke, and the media inside them; anything else in
+    // between blocks it.
+    pub fn transmittance(&self, from: Point3<f64>, to: Point3<f64>) -> Vector3<f64> {
+        let distance = (to - from).norm();
+        self.transmittance_towards(from, (to - from) / distance, distance)
+    }
+
+    // as transmittance, along a direction for a distance that may be infinite
+    pub fn transmittance_towards(&self, from: Point3<f64>, direction: Vector3<f64>, distance: f64) -> Vector3<f64> {
+        self.shadow_transmittance(from, direction, distance, false)
     }
 
-    pub fn lights(&self) -> Vec<Sphere> {
-        self.lights.iter().map(|i| {
-            self.objects[*i]
-        }).collect::<Vec<_>>()
+    // As transmittance_towards, for a delta light seen from inside a medium.
+    // Light from those can only be found by sampling it, so the ray may also
+    // leave through the first refracting boundary it meets from inside,
+    // weighted by the Fresnel transmission there and without bending, so
+    // that such lights reach into objects like marble and skin.
+    pub fn delta_transmittance_towards(&self, from: Point3<f64>, direction: Vector3<f64>, distance: f64) -> Vector3<f64> {
+        self.shadow_transmittance(from, direction, distance, true)
+    }
+
+    fn shadow_transmittance(&self, from: Point3<f64>, direction: Vector3<f64>, distance: f64, leaving: bool) -> Vector3<f64> {
+        let mut leaving = leaving;
+        let mut remaining = distance;
+        let mut ray = Ray { origin: from, direction };
+        let mut transmittance = Vector3::new(1.0, 1.0, 1.0);
+        while transmittance != Vector3::zeros() {
+            let hit = self.intersect(&ray).filter(|hit| hit.distance < remaining);
+            let stretch = hit.as_ref().map_or(remaining, |hit| hit.distance);
+            transmittance = transmittance.component_mul(&self.media_transmittance(&ray, stretch));
+            match hit {
+                Some(hit) => {
+                    let cos_incident = -direction.dot(&hit.normal);
+                    if leaving && cos_incident < 0.0 && hit.material.is_enclosure() && !hit.material.is_index_matched() {
+                        let index = hit.material.interior_index(None);
+                        transmittance *= 1.0 - fresnel::dielectric(cos_incident, 1.0, index);
+                        leaving = false;
+                    } else if !hit.material.is_index_matched() {
+                        transmittance *= 1.0 - hit.material.opacity(hit.uv);
+                    }
+                    remaining -= hit.distance;
+                    ray.origin = hit.hit;
+                }
+                None => break
+            }
+        }
+        transmittance
+    }
+
+    // transmittance of the media inside objects along a stretch of a ray
+    fn media_transmittance(&self, ray: &Ray, distance: f64) -> Vector3<f64> {
+        self.objects.iter().filter_map(|object| {
+            let medium = object.material().medium()?;
+            let (enter, leave) = object.chord(ray)?;
+            let (start, end) = (enter.max(0.0), leave.min(distance));
+            if end <= start {
+                return None;
+            }
+            let inside = Ray { origin: ray.origin + ray.direction * start, direction: ray.direction };
+            Some(medium.transmittance_along(&inside, end - start, object))
+        }).fold(Vector3::new(1.0, 1.0, 1.0), |total, transmittance| total.component_mul(&transmittance))
+    }
+
+    // what a camera ray that meets nothing sees, if not the lights beyond
+    pub fn camera_background(&self, direction: &Vector3<f64>) -> Option<Vector3<f64>> {
+        self.camera_background.as_ref().map(|background| background.radiance(direction))
+    }
+
+    // Picks a light, emitter or otherwise, that is likely to light a point
+    // and samples the light it sends there, with the light's group. The
+    // density includes the chance of picking the light, and a delta light's
+    // radiance is divided by it.
+    pub fn sample_light(&self, from: Point3<f64>, pick: f64, u: f64, v: f64) -> Option<(LightSample, usize)> {
+        let lights = self.light_index();
+        let (i, probability) = lights.sampler.sample(from, pick)?;
+        let mut sample = match self.emitters.get(i) {
+            Some(&index) => {
+                let emitter = &self.objects[index];
+                let (point, normal, pdf) = emitter.sample_towards(from, u, v);
+                let to_light = point - from;
+                let distance = to_light.norm();
+                let direction = to_light / distance;
+                LightSample {
+                    direction,
+                    distance,
+                    radiance: emitter.material().emit(emitter.uv(&normal), -direction.dot(&normal)),
+                    pdf: Some(pdf),
+                }
+            }
+            None => self.lights[i - self.emitters.len()].0.sample(from, u, v)
+        };
+
+        match sample.pdf {
+            Some(pdf) => sample.pdf = Some(pdf * probability),
+            None => sample.radiance /= probability
+        }
+        Some((sample, lights.groups[i]))
+    }
+
+    pub fn emitter_group(&self, emitter: &Sphere) -> usize {
+        let lights = self.light_index();
+        lights.emitter_lights.get(&emitter.index()).map_or(0, |light| lights.groups[*light])
+    }
+
+    // density of sample_light picking a point on an emitter
+    pub fn emitter_pdf(&self, from: Point3<f64>, emitter: &Sphere, point: Point3<f64>) -> f64 {
+        let lights = self.light_index();
+        match lights.emitter_lights.get(&emitter.index()) {
+            Some(&light) => lights.sampler.probability(from, light) * emitter.pdf_towards(from, point),
+            None => 0.0
+        }
+    }
+
+    // radiance of the lights seen by a ray leaving the scene, each with the
+    // density of sample_light picking its direction and the light's group
+    pub fn escaped_light(&self, from: Point3<f64>, direction: &Vector3<f64>) -> Vec<(Vector3<f64>, f64, usize)> {
+        let lights = self.light_index();
+        self.lights.iter().enumerate().filter_map(|(i, (light, _))| {
+            let index = self.emitters.len() + i;
+            light.radiance_towards(from, direction).map(|(radiance, pdf)| {
+                (radiance, pdf * lights.sampler.probability(from, index), lights.groups[index])
+            })
+        }).collect()
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::background::Background;
     use crate::camera::Camera;
     use crate::material::Material;
+    use crate::medium::Medium;
     use crate::ray::Ray;
     use crate::sphere::Sphere;
+    use crate::texture::Texture;
     use nalgebra::{Point3, Vector3};
 
     #[test]
@@ -95,14 +307,14 @@ mod test {
         );
 
         let objects = vec![
-            Sphere::new(Point3::new(-1005.0, 0.0, -8.0), 1000.0, blue_plastic),
-            Sphere::new(Point3::new(1005.0, 0.0, -8.0), 1000.0, blue_plastic),
-            Sphere::new(Point3::new(0.0, -1003.0, -8.0), 1000.0, blue_plastic),
-            Sphere::new(Point3::new(0.0, 1003.0, -8.0), 1000.0, blue_plastic),
-            Sphere::new(Point3::new(0.0, 0.0, -1010.0), 1000.0, blue_plastic),
-            Sphere::new(Point3::new(0.0, 13.0, -8.0), 10.5, blue_plastic),
-            Sphere::new(Point3::new(1.0, -2.0, -7.0), 1.0, blue_plastic),
-            Sphere::new(Point3::new(-0.75, -2.0, -5.0), 1.0, blue_plastic),
+            Sphere::new(0, Point3::new(-1005.0, 0.0, -8.0), 1000.0, blue_plastic.clone()),
+            Sphere::new(1, Point3::new(1005.0, 0.0, -8.0), 1000.0, blue_plastic.clone()),
+            Sphere::new(2, Point3::new(0.0, -1003.0, -8.0), 1000.0, blue_plastic.clone()),
+            Sphere::new(3, Point3::new(0.0, 1003.0, -8.0), 1000.0, blue_plastic.clone()),
+            Sphere::new(4, Point3::new(0.0, 0.0, -1010.0), 1000.0, blue_plastic.clone()),
+            Sphere::new(5, Point3::new(0.0, 13.0, -8.0), 10.5, blue_plastic.clone()),
+            Sphere::new(6, Point3::new(1.0, -2.0, -7.0), 1.0, blue_plastic.clone()),
+            Sphere::new(7, Point3::new(-0.75, -2.0, -5.0), 1.0, blue_plastic),
         ];
 
         let camera = Camera::new(
@@ -131,4 +343,95 @@ mod test {
             )
         );
     }
+
+    #[test]
+    fn rays_that_miss_everything_have_no_intersection() {
+        let material = Material::new(
+            Vector3::new(0.5, 0.5, 0.5),
+            1.0,
+            0.0,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.04, 0.04, 0.04),
+            0.0,
+            0.0,
+        );
+        let camera = Camera::new(Point3::new(0.0, 0.0, 7.0), 0.024, 0.040, 15.0, 1.4, 0.0, 0.0);
+        let scene = Scene::new(vec![Sphere::new(0, Point3::new(0.0, 0.0, 0.0), 1.0, material)], camera);
+
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 3.0),
+            direction: Vector3::new(0.0, 1.0, 0.0),
+        };
+        assert!(scene.intersect(&ray).is_none());
+    }
+
+    #[test]
+    fn lights_are_split_into_named_groups() {
+        let camera = Camera::new(Point3::new(0.0, 0.0, 7.0), 0.024, 0.040, 15.0, 1.4, 0.0, 0.0);
+        let lamp = Material::emitter(Vector3::repeat(10.0));
+        let mut scene = Scene::new(vec![
+            Sphere::new(0, Point3::new(-2.0, 0.0, 0.0), 0.5, lamp.clone().with_light_group("key")),
+            Sphere::new(1, Point3::new(2.0, 0.0, 0.0), 0.5, lamp.clone()),
+        ], camera);
+        scene.add_grouped_light(Light::point(Point3::new(0.0, 3.0, 0.0), Vector3::repeat(1.0)), "fill");
+        scene.add_grouped_light(Light::point(Point3::new(0.0, -3.0, 0.0), Vector3::repeat(1.0)), "key");
+        scene.add_object(Sphere::new(2, Point3::new(0.0, 0.0, -2.0), 0.5, lamp.with_light_group("rim")), true);
+
+        assert_eq!(scene.light_groups(), ["default", "key", "rim", "fill"]);
+        assert_eq!(scene.emitter_group(&scene.objects[0]), 1);
+        assert_eq!(scene.emitter_group(&scene.objects[1]), 0);
+        assert_eq!(scene.emitter_group(&scene.objects[2]), 2);
+
+        let mut seen = [0; 4];
+        for i in 0..100 {
+            let (_, group) = scene.sample_light(Point3::origin(), (i as f64 + 0.5) / 100.0, 0.5, 0.5).unwrap();
+            seen[group] += 1;
+        }
+        assert!(seen.iter().all(|count| *count > 0));
+    }
+
+    #[test]
+    fn transmittance_passes_through_cutouts_only() {
+        let material = || Material::new(
+            Vector3::new(0.5, 0.5, 0.5),
+            1.0,
+            0.0,
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.04, 0.04, 0.04),
+            0.0,
+            0.0,
+        );
+        let leaf = material().with_opacity(Texture::scalar(0.25));
+
+        let camera = Camera::new(Point3::new(0.0, 0.0, 7.0), 0.024, 0.040, 15.0, 1.4, 0.0, 0.0);
+        let scene = Scene::new(vec![
+            Sphere::new(0, Point3::new(0.0, 0.0, 0.0), 1.0, leaf),
+            Sphere::new(1, Point3::new(0.0, 5.0, 0.0), 1.0, material()),
+        ], camera);
+
+        // both sides of the cutout sphere are crossed
+        let through = scene.transmittance(Point3::new(0.0, 0.0, 3.0), Point3::new(0.0, 0.0, -3.0));
+        assert!((through - Vector3::repeat(0.75 * 0.75)).norm() < 1e-12);
+
+        let blocked = scene.transmittance(Point3::new(0.0, 3.0, 0.0), Point3::new(0.0, 7.0, 0.0));
+        assert_eq!(blocked, Vector3::zeros());
+
+        let clear = scene.transmittance(Point3::new(3.0, 0.0, 0.0), Point3::new(3.0, 5.0, 0.0));
+        assert_eq!(clear, Vector3::repeat(1.0));
+    }
+
+    #[test]
+    fn transmittance_is_attenuated_by_smoke() {
+        let smoke = Medium::new(Vector3::new(0.1, 0.2, 0.3), Vector3::new(0.2, 0.2, 0.2));
+        let camera = Camera::new(Point3::new(0.0, 0.0, 7.0), 0.024, 0.040, 15.0, 1.4, 0.0, 0.0);
+        let scene = Scene::new(vec![
+            Sphere::new(0, Point3::new(0.0, 0.0, 0.0), 1.0, Material::volume(smoke.clone())),
+        ], camera);
+
+        // across the whole sphere, and from inside it out to infinity
+        let across = scene.transmittance(Point3::new(0.0, 0.0, 3.0), Point3::new(0.0, 0.0, -3.0));
+        assert!((across - smoke.transmittance(2.0)).norm() < 1e-9);
+        let out = scene.transmittance_towards(Point3::new(0.0, 0.5, 0.0), Vector3::new(0.0, 1.0, 0.0), f64::INFINITY);
+        assert!((out - smoke.transmittance(0.5)).norm() < 1e-9);
+    }
 }
@@ -1,30 +1,146 @@
 use nalgebra::{Vector3, Point3};
+use std::f64;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
 
 use crate::sphere::Sphere;
+use crate::background::Background;
 use crate::material::Material;
+use crate::bxdf::{
+    Bxdf, Combined, Lambertian, Layered, Microfacet, MicrofacetFresnel, OrenNayar, Sheen
+};
+use crate::fresnel::Conductor;
+use crate::medium::Medium;
 use crate::scene::Scene;
+use crate::sky::Sky;
 use crate::camera::Camera;
+use crate::emission;
+use crate::environment::{Environment, Portal};
+use crate::ies::IesProfile;
+use crate::light::Light;
+use crate::volume::VoxelGrid;
+use crate::material_library::MaterialLibrary;
+use crate::image::Image;
+use crate::texture::Texture;
 
 pub fn load_scene(name: &str) -> Option<Scene> {
+  load_scene_from_library(name, &MaterialLibrary::presets())
+}
+
+// Materials in the library replace presets of the same name wherever the
+// scene uses them.
+pub fn load_scene_with_materials(name: &str, materials: MaterialLibrary) -> Option<Scene> {
+  let mut library = MaterialLibrary::presets();
+  library.extend(materials);
+  load_scene_from_library(name, &library)
+}
+
+fn load_scene_from_library(name: &str, library: &MaterialLibrary) -> Option<Scene> {
   match name {
-    "box" => Some(load_box_scene()),
-    "spheres" => Some(load_spheres_scene()),
+    "box" => Some(load_box_scene(library)),
+    "spheres" => Some(load_spheres_scene(library)),
     "sphere grid" => Some(load_sphere_grid()),
+    "smoke" => Some(load_smoke_scene(library)),
+    "materials" => Some(load_materials_scene(library)),
+    "nested" => Some(load_nested_scene(library)),
+    "cutout" => Some(load_cutout_scene(library)),
+    "catcher" => Some(load_catcher_scene(library)),
+    "emitters" => Some(load_emitters_scene(library)),
+    "lights" => Some(load_lights_scene(library)),
+    "studio" => Some(load_studio_scene(library)),
+    "daylight" => Some(load_daylight_scene(library)),
+    "fixtures" => Some(load_fixtures_scene(library)),
+    "interior" => Some(load_interior_scene(library)),
+    "overcast" => Some(load_overcast_scene(library)),
     _ => None
   }
 }
 
+// A window or other opening the environment lights an interior through, as a
+// corner and the two edges from it spanning the opening.
+pub type PortalQuad = (Point3<f64>, Vector3<f64>, Vector3<f64>);
+
+// Lights a scene with an equirectangular .hdr or .pfm image, turned about the
+// vertical by the rotation (in radians) and with its radiance scaled by the
+// intensity. Interiors seeing the environment only through openings should
+// name them as portals.
+pub fn add_environment(
+  scene: &mut Scene,
+  path: impl AsRef<Path>,
+  rotation: f64,
+  intensity: f64,
+  portals: &[PortalQuad]
+) -> io::Result<()> {
+  let path = path.as_ref();
+  let image = match path.extension().and_then(|extension| extension.to_str()) {
+    Some("hdr") | Some("pic") => Image::load_hdr(path)?,
+    Some("pfm") => Image::load_pfm(path)?,
+    _ => return Err(Error::new(ErrorKind::InvalidInput, "environment maps should be .hdr or .pfm images"))
+  };
+  let environment = Environment::new(Arc::new(image), rotation, intensity);
+  scene.add_light(Light::environment(environment.with_portals(to_portals(portals))));
+  Ok(())
+}
+
+// Lights a scene with a clear sky and the sun, placed by its elevation and
+// azimuth in radians. Daylight is thousands of nits, so the intensity acts as
+// the camera's exposure. Interiors name their windows as portals.
+pub fn add_daylight(
+  scene: &mut Scene,
+  elevation: f64,
+  azimuth: f64,
+  turbidity: f64,
+  intensity: f64,
+  portals: &[PortalQuad]
+) {
+  let sky = Sky::new(elevation, azimuth, turbidity);
+  let environment = sky.environment(256, 128, intensity);
+  scene.add_light(Light::environment(environment.with_portals(to_portals(portals))));
+  scene.add_light(sky.sun(intensity));
+}
+
+// Lights a scene with a plain background, which the camera sees too unless
+// the scene sets a background of its own for it.
+pub fn add_background(scene: &mut Scene, background: &Background) {
+  if let Some(environment) = background.environment() {
+    scene.add_light(Light::environment(environment));
+  }
+}
+
+fn to_portals(quads: &[PortalQuad]) -> Vec<Portal> {
+  quads.iter().map(|&(corner, edge_u, edge_v)| Portal::new(corner, edge_u, edge_v)).collect()
+}
+
+// Adds a light shaped like the fixture measured in an IES file, aimed along
+// the direction with its C0 plane turned towards c0, and coloured like a
+// blackbody at the temperature.
+pub fn add_ies_light(
+  scene: &mut Scene,
+  path: impl AsRef<Path>,
+  position: Point3<f64>,
+  aim: Vector3<f64>,
+  c0: Vector3<f64>,
+  kelvin: f64
+) -> io::Result<()> {
+  let profile = IesProfile::load(path)?;
+  let light = Light::spot(position, aim, emission::blackbody(kelvin), f64::consts::PI, 0.0);
+  scene.add_light(light.with_profile(Arc::new(profile), c0));
+  Ok(())
+}
+
+fn preset(library: &MaterialLibrary, name: &str) -> Material {
+  library.get(name).unwrap_or_else(|| panic!("no {} material", name))
+}
+
+// a spherical blackbody lamp radiating the given power
+fn lamp(index: usize, center: Point3<f64>, radius: f64, kelvin: f64, watts: f64) -> Sphere {
+  let area = 4.0 * f64::consts::PI * radius * radius;
+  Sphere::new(index, center, radius, Material::emitter(emission::watts(kelvin, watts, area)))
+}
+
 fn load_sphere_grid() -> Scene {
-    const INTENSITY: f64 = 800.0;
-    let bright_light = Material::new(
-        Vector3::new(0.0, 0.0, 0.0),
-        1.0,
-        1.0,
-        Vector3::new(INTENSITY, INTENSITY, INTENSITY),
-        Vector3::new(0.0, 0.0, 0.0),
-        0.0,
-        0.0
-    );
+    let bright_light = Material::emitter(emission::nits(5500.0, 800.0));
 
     let mut objects = Vec::new();
     for i in (0..5) {
@@ -66,76 +182,22 @@ fn load_sphere_grid() -> Scene {
 }
 
 
-fn load_spheres_scene() -> Scene {
-  let bright_light = Material::new(
-        Vector3::new(0.0, 0.0, 0.0),
-        1.0,
-        1.0,
-        Vector3::new(700.0, 700.0, 700.0),
-        Vector3::new(0.0, 0.0, 0.0),
-        0.0,
-        0.0
-    );
+fn load_spheres_scene(library: &MaterialLibrary) -> Scene {
+    let white = preset(library, "white");
 
-    let white_lambert = Material::new(
-        Vector3::new(1.0, 1.0, 1.0),
-        1.0,
-        0.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.03, 0.03, 0.03),
-        0.0,
-        0.0
-    );
+    let blue_paint = Material::from_bxdf(Arc::new(Layered::clearcoat(
+        1.5,
+        0.05,
+        Vector3::new(0.4, 0.3, 0.0),
+        0.1,
+        Arc::new(Lambertian::new(Vector3::new(0.1, 0.1, 1.0)))
+    )));
 
-    let blue_plastic = Material::new(
-        Vector3::new(0.1, 0.1, 1.0),
-        1.0,
-        0.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.04, 0.04, 0.04),
-        0.0,
-        0.2
-    );
+    let silver = preset(library, "silver");
 
-    let red_plastic = Material::new(
-        Vector3::new(1.0, 0.0, 0.0),
-        1.0,
-        0.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.04, 0.04, 0.04),
-        0.0,
-        0.2
-    );
+    let gold = preset(library, "gold");
 
-    let silver = Material::new(
-        Vector3::new(0.972, 0.960, 0.915),
-        1.0,
-        0.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.972, 0.960, 0.915),
-        0.9,
-        1.0
-    );
-
-    let gold = Material::new(
-        Vector3::new(0.0, 0.0, 0.0),
-        1.0,
-        0.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(1.022, 0.782, 0.344),
-        1.0,
-        0.7
-    );
-
-    let glass = Material::new(
-        Vector3::new(0.0, 0.0, 0.0),
-        1.6,
-        1.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.04, 0.04, 0.04),
-        0.0,
-        0.0
-    );
+    let glass = preset(library, "glass");
 
     let green_glass = Material::new(
         Vector3::new(0.0, 1.0, 0.0),
@@ -145,15 +207,18 @@ fn load_spheres_scene() -> Scene {
         Vector3::new(0.04, 0.04, 0.04),
         0.0,
         0.0
-    );
+    ).with_medium(Medium::new(
+        Vector3::new(0.8, 0.05, 0.8),
+        Vector3::new(0.0, 0.0, 0.0)
+    ));
 
     let objects = vec![
         Sphere::new(0, Point3::new(-3.3, 1.0, -4.3), 1.0, gold),
-        Sphere::new(1, Point3::new(-1.1, 1.0, -5.0), 1.0, blue_plastic),
+        Sphere::new(1, Point3::new(-1.1, 1.0, -5.0), 1.0, blue_paint),
         Sphere::new(2, Point3::new(1.0, 1.0, -5.0), 1.0, silver),
         Sphere::new(3, Point3::new(3.2, 1.0, -4.6), 1.0, green_glass),
-        Sphere::new(4, Point3::new(0.5, -1000.0, -8.0), 1000.0, white_lambert),
-        Sphere::new(5, Point3::new(-8.0, 3.0, -1.0), 2.0, bright_light)
+        Sphere::new(4, Point3::new(0.5, -1000.0, -8.0), 1000.0, white),
+        lamp(5, Point3::new(-8.0, 3.0, -1.0), 2.0, 5500.0, 1200.0)
     ];
 
     let camera = Camera::new(
@@ -169,87 +234,481 @@ fn load_spheres_scene() -> Scene {
     Scene::new(objects, camera)
 }
 
-fn load_box_scene() -> Scene {
-  let bright_light = Material::new(
-        Vector3::new(0.0, 0.0, 0.0),
-        1.0,
-        1.0,
-        Vector3::new(355.0, 355.0, 355.0),
-        Vector3::new(0.0, 0.0, 0.0),
+fn load_box_scene(library: &MaterialLibrary) -> Scene {
+    // a softly lit ceiling panel
+    let bright_light = Material::emitter(emission::nits(5500.0, 355.0));
+
+    let white = preset(library, "white");
+
+    let blue_plastic = preset(library, "blue_plastic");
+
+    let red_plastic = preset(library, "red_plastic");
+
+    let silver = preset(library, "silver");
+
+    let glass = preset(library, "glass");
+
+    let objects = vec![
+        Sphere::new(0, Point3::new(-1005.0, 0.0, -8.0), 1000.0, blue_plastic),
+        Sphere::new(1, Point3::new(1005.0, 0.0, -8.0), 1000.0, red_plastic),
+        Sphere::new(2, Point3::new(0.0, -1003.0, -8.0), 1000.0, white.clone()),
+        Sphere::new(3, Point3::new(0.0, 1003.0, -8.0), 1000.0, white.clone()),
+        Sphere::new(4, Point3::new(0.0, 0.0, -1010.0), 1000.0, white),
+        Sphere::new(5, Point3::new(0.0, 13.0, -8.0), 10.5, bright_light),
+        Sphere::new(6, Point3::new(1.0, -2.0, -7.0), 1.0, silver),
+        Sphere::new(7, Point3::new(-0.75, -2.0, -5.0), 1.0, glass)
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 0.0, 7.0),
+        0.024,
+        0.040,
+        15.0,
+        1.4,
         0.0,
         0.0
     );
 
-    let white_lambert = Material::new(
-        Vector3::new(1.0, 1.0, 1.0),
-        1.0,
+    Scene::new(objects, camera)
+}
+
+fn load_smoke_scene(library: &MaterialLibrary) -> Scene {
+    let white = preset(library, "white");
+
+    // a lumpy ball of smoke that thins out towards the edge of its bounds
+    let grid = VoxelGrid::parse(include_bytes!("smoke.vol")).expect("smoke grid should parse");
+
+    let smoke = Material::volume(
+        Medium::new(
+            Vector3::new(0.2, 0.2, 0.2),
+            Vector3::new(3.0, 3.0, 3.0)
+        )
+        .with_density(Arc::new(grid))
+        .with_anisotropy(0.4)
+    );
+
+    let objects = vec![
+        Sphere::new(0, Point3::new(0.0, -1000.0, -8.0), 1000.0, white),
+        Sphere::new(1, Point3::new(0.0, 1.5, -5.0), 1.5, smoke),
+        lamp(2, Point3::new(-8.0, 6.0, -1.0), 2.0, 5500.0, 1200.0)
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
         0.0,
+        10.0
+    );
+
+    Scene::new(objects, camera)
+}
+
+fn load_materials_scene(library: &MaterialLibrary) -> Scene {
+    let white = preset(library, "white");
+
+    let clay = preset(library, "clay");
+
+    let velvet_lobes: Vec<Arc<dyn Bxdf>> = vec![
+        Arc::new(OrenNayar::new(Vector3::new(0.25, 0.0, 0.05), 0.5)),
+        Arc::new(Sheen::new(Vector3::new(1.0, 0.5, 0.6), 0.3))
+    ];
+    let velvet = Material::from_bxdf(Arc::new(Combined::new(velvet_lobes)));
+
+    let green_paint = Material::from_bxdf(Arc::new(Layered::clearcoat(
+        1.5,
+        0.05,
         Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.03, 0.03, 0.03),
+        0.1,
+        Arc::new(Lambertian::new(Vector3::new(0.05, 0.5, 0.1)))
+    )));
+
+    let brushed_aluminium = Material::from_bxdf(Arc::new(Microfacet::anisotropic(
+        0.05,
+        0.5,
         0.0,
-        0.0
+        MicrofacetFresnel::Conductor(Conductor::named("Al").unwrap())
+    )));
+
+    let objects = vec![
+        Sphere::new(0, Point3::new(-3.3, 1.0, -4.3), 1.0, clay),
+        Sphere::new(1, Point3::new(-1.1, 1.0, -5.0), 1.0, velvet),
+        Sphere::new(2, Point3::new(1.0, 1.0, -5.0), 1.0, green_paint),
+        Sphere::new(3, Point3::new(3.2, 1.0, -4.6), 1.0, brushed_aluminium),
+        Sphere::new(4, Point3::new(0.5, -1000.0, -8.0), 1000.0, white),
+        lamp(5, Point3::new(-8.0, 3.0, -1.0), 2.0, 5500.0, 1200.0)
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 6.0, 8.0),
+        0.024,
+        0.055,
+        14.0,
+        1.4,
+        0.0,
+        25.0
     );
 
-    let blue_plastic = Material::new(
-        Vector3::new(0.1, 0.1, 1.0),
+    Scene::new(objects, camera)
+}
+
+// overlapping transmissive objects: ice floating in a ball of water, with a
+// glass ball pushed into the water from the side
+fn load_nested_scene(library: &MaterialLibrary) -> Scene {
+    let white = preset(library, "white");
+
+    let water = preset(library, "water");
+
+    let ice = preset(library, "ice");
+
+    let glass = preset(library, "glass").with_priority(3);
+
+    let objects = vec![
+        Sphere::new(0, Point3::new(0.0, 1.5, -5.0), 1.5, water),
+        Sphere::new(1, Point3::new(-0.3, 2.3, -4.8), 0.6, ice),
+        Sphere::new(2, Point3::new(1.6, 1.0, -4.5), 0.8, glass),
+        Sphere::new(3, Point3::new(0.0, -1000.0, -8.0), 1000.0, white),
+        lamp(4, Point3::new(-8.0, 6.0, -1.0), 2.0, 5500.0, 1200.0)
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
+        0.0,
+        10.0
+    );
+
+    Scene::new(objects, camera)
+}
+
+// a gold ball inside a cage cut out of a sphere with an opacity mask
+fn load_cutout_scene(library: &MaterialLibrary) -> Scene {
+    let white = preset(library, "white");
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 32;
+    let bars = (0..WIDTH * HEIGHT).map(|i| {
+        let (x, y) = (i % WIDTH, i / WIDTH);
+        let solid = x % 8 < 2 || y % 8 < 2;
+        Vector3::repeat(if solid { 1.0 } else { 0.0 })
+    }).collect();
+    let mask = Texture::image(Arc::new(Image::new(WIDTH, HEIGHT, bars)), Vector3::repeat(1.0));
+
+    let cage = Material::new(
+        Vector3::new(0.6, 0.1, 0.1),
         1.0,
         0.0,
         Vector3::new(0.0, 0.0, 0.0),
         Vector3::new(0.04, 0.04, 0.04),
         0.0,
-        0.2
+        0.0
+    ).with_opacity(mask);
+
+    let gold = preset(library, "gold");
+
+    let objects = vec![
+        Sphere::new(0, Point3::new(0.0, 1.5, -5.0), 1.5, cage),
+        Sphere::new(1, Point3::new(0.0, 1.2, -5.0), 0.8, gold),
+        Sphere::new(2, Point3::new(0.0, -1000.0, -8.0), 1000.0, white),
+        lamp(3, Point3::new(-8.0, 6.0, -1.0), 2.0, 5500.0, 1200.0)
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
+        0.0,
+        10.0
+    );
+
+    Scene::new(objects, camera)
+}
+
+// objects to composite over a photograph, casting shadows and reflections
+// onto its polished floor and passing behind a real object held out of the
+// render
+fn load_catcher_scene(library: &MaterialLibrary) -> Scene {
+    let objects = vec![
+        Sphere::new(0, Point3::new(-1.2, 1.0, -5.0), 1.0, preset(library, "gold")),
+        Sphere::new(1, Point3::new(1.2, 1.0, -5.0), 1.0, preset(library, "red_plastic")),
+        Sphere::new(2, Point3::new(0.3, 0.6, -2.5), 0.6, Material::holdout()),
+        Sphere::new(3, Point3::new(0.0, -1000.0, -8.0), 1000.0, Material::shadow_catcher(0.04, 0.9)),
+        lamp(4, Point3::new(-8.0, 6.0, -1.0), 2.0, 5500.0, 1200.0)
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
+        0.0,
+        10.0
     );
 
-    let red_plastic = Material::new(
+    Scene::new(objects, camera)
+}
+
+// a glowing screen showing colour bars, next to a lamp whose light is
+// narrowed towards its surface normal
+fn load_emitters_scene(library: &MaterialLibrary) -> Scene {
+    const BARS: usize = 8;
+    let colours = [
+        Vector3::new(1.0, 1.0, 1.0),
+        Vector3::new(1.0, 1.0, 0.0),
+        Vector3::new(0.0, 1.0, 1.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(1.0, 0.0, 1.0),
         Vector3::new(1.0, 0.0, 0.0),
-        1.0,
+        Vector3::new(0.0, 0.0, 1.0),
+        Vector3::new(0.0, 0.0, 0.0)
+    ];
+    let bars = Image::new(BARS, 1, colours.to_vec());
+
+    let screen = Material::emitter(emission::nits(6500.0, 200.0))
+        .with_emission_texture(Texture::image(Arc::new(bars), Vector3::repeat(1.0)))
+        .one_sided()
+        .with_light_group("screen");
+
+    let spot = Material::emitter(emission::nits(3200.0, 2000.0))
+        .with_falloff(8.0)
+        .with_light_group("lamp");
+
+    let objects = vec![
+        Sphere::new(0, Point3::new(-1.5, 1.2, -5.0), 1.2, screen),
+        Sphere::new(1, Point3::new(1.8, 0.6, -4.5), 0.6, spot),
+        Sphere::new(2, Point3::new(0.5, 0.5, -3.0), 0.5, preset(library, "silver")),
+        Sphere::new(3, Point3::new(0.0, -1000.0, -8.0), 1000.0, preset(library, "white"))
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
         0.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.04, 0.04, 0.04),
+        10.0
+    );
+
+    Scene::new(objects, camera)
+}
+
+// spheres under a low evening sun, a warm bulb and a spotlight, none of which
+// are objects in the scene
+fn load_lights_scene(library: &MaterialLibrary) -> Scene {
+    let objects = vec![
+        Sphere::new(0, Point3::new(-1.5, 1.0, -5.0), 1.0, preset(library, "clay")),
+        Sphere::new(1, Point3::new(1.2, 0.8, -4.0), 0.8, preset(library, "glass")),
+        Sphere::new(2, Point3::new(0.2, 0.4, -2.5), 0.4, preset(library, "copper")),
+        Sphere::new(3, Point3::new(0.0, -1000.0, -8.0), 1000.0, preset(library, "white"))
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
         0.0,
-        0.2
+        10.0
     );
 
-    let silver = Material::new(
-        Vector3::new(0.972, 0.960, 0.915),
-        1.0,
+    let mut scene = Scene::new(objects, camera);
+    scene.add_light(Light::directional(
+        Vector3::new(-1.0, 0.6, 0.3),
+        emission::blackbody(4000.0) * 400.0,
+        0.0093
+    ));
+    scene.add_light(Light::point(Point3::new(2.5, 2.5, -1.5), emission::blackbody(2700.0) * 20.0));
+    scene.add_light(Light::spot(
+        Point3::new(-1.5, 5.0, -5.0),
+        Vector3::new(0.0, -1.0, 0.0),
+        emission::blackbody(5000.0) * 1000.0,
+        0.3,
+        0.1
+    ));
+    scene
+}
+
+// Product shot lit only by an environment of two softboxes and a dim
+// backdrop, in front of a grey sweep that only the camera sees. Swap in a
+// photographed studio with add_environment.
+fn load_studio_scene(library: &MaterialLibrary) -> Scene {
+    let objects = vec![
+        Sphere::new(0, Point3::new(-1.2, 1.0, -5.0), 1.0, preset(library, "chrome")),
+        Sphere::new(1, Point3::new(1.2, 1.0, -5.0), 1.0, preset(library, "blue_plastic")),
+        Sphere::new(2, Point3::new(0.0, -1000.0, -8.0), 1000.0, preset(library, "white"))
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
+        0.0,
+        10.0
+    );
+
+    let (width, height) = (128, 64);
+    let pixels = (0..width * height).map(|i| {
+        let (x, y) = (i % width, i / width);
+        let softbox = |left: usize, top: usize| {
+            (left..left + 10).contains(&x) && (top..top + 6).contains(&y)
+        };
+        if softbox(20, 12) || softbox(90, 18) {
+            emission::nits(5600.0, 2000.0)
+        } else {
+            Vector3::repeat(0.5 * (1.0 - y as f64 / height as f64))
+        }
+    }).collect();
+
+    let mut scene = Scene::new(objects, camera);
+    scene.add_light(Light::environment(Environment::new(
+        Arc::new(Image::new(width, height, pixels)),
         0.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.972, 0.960, 0.915),
-        0.9,
         1.0
+    )));
+    scene.set_camera_background(Background::gradient(Vector3::repeat(60.0), Vector3::repeat(140.0)));
+    scene
+}
+
+// late afternoon light over a courtyard, from the sky model alone
+fn load_daylight_scene(library: &MaterialLibrary) -> Scene {
+    let objects = vec![
+        Sphere::new(0, Point3::new(-2.0, 1.5, -7.0), 1.5, preset(library, "marble")),
+        Sphere::new(1, Point3::new(1.5, 1.0, -5.0), 1.0, preset(library, "clay")),
+        Sphere::new(2, Point3::new(0.0, 0.5, -3.0), 0.5, preset(library, "glass")),
+        Sphere::new(3, Point3::new(0.0, -1000.0, -8.0), 1000.0, preset(library, "white"))
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
+        0.0,
+        10.0
     );
 
-    let glass = Material::new(
-        Vector3::new(0.0, 0.0, 0.0),
-        1.6,
-        1.0,
-        Vector3::new(0.0, 0.0, 0.0),
-        Vector3::new(0.04, 0.04, 0.04),
+    let mut scene = Scene::new(objects, camera);
+    add_daylight(&mut scene, 0.35, 2.5, 3.0, 0.01, &[]);
+    scene
+}
+
+// a row of recessed downlights over spheres, with one aimed at the wall
+fn load_fixtures_scene(library: &MaterialLibrary) -> Scene {
+    let objects = vec![
+        Sphere::new(0, Point3::new(-2.0, 0.8, -5.0), 0.8, preset(library, "red_plastic")),
+        Sphere::new(1, Point3::new(0.0, 0.8, -5.0), 0.8, preset(library, "white")),
+        Sphere::new(2, Point3::new(2.0, 0.8, -5.0), 0.8, preset(library, "gold")),
+        Sphere::new(3, Point3::new(0.0, -1000.0, -8.0), 1000.0, preset(library, "white")),
+        Sphere::new(4, Point3::new(0.0, 0.0, -1008.0), 1000.0, preset(library, "white"))
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
+        1.4,
         0.0,
+        10.0
+    );
+
+    let downlight = Arc::new(
+        IesProfile::parse(include_str!("downlight.ies")).expect("downlight profile should parse")
+    );
+    let mut scene = Scene::new(objects, camera);
+    for x in [-2.0, 0.0, 2.0].iter() {
+        let light = Light::point(Point3::new(*x, 4.0, -5.0), emission::blackbody(3000.0) * 1.5);
+        scene.add_grouped_light(light.with_profile(downlight.clone(), Vector3::new(1.0, 0.0, 0.0)), "downlights");
+    }
+    let wall_washer = Light::spot(
+        Point3::new(-3.5, 4.0, -6.0),
+        Vector3::new(0.3, -1.0, -0.6),
+        emission::blackbody(4000.0) * 1.5,
+        f64::consts::PI,
         0.0
     );
+    scene.add_grouped_light(wall_washer.with_profile(downlight, Vector3::new(0.0, 0.0, -1.0)), "wall washer");
+    scene
+}
+
+// A room lit by daylight coming in through a window in its wall, which is
+// named as a portal so the sky is sampled through it.
+fn load_interior_scene(library: &MaterialLibrary) -> Scene {
+    // the room is the inside of a sphere, with the window cut out of it
+    // facing +x
+    const WIDTH: usize = 128;
+    const HEIGHT: usize = 64;
+    let window = (0..WIDTH * HEIGHT).map(|i| {
+        let (u, v) = ((i % WIDTH) as f64 / WIDTH as f64, (i / WIDTH) as f64 / HEIGHT as f64);
+        let open = (u - 0.5).abs() < 0.06 && v > 0.38 && v < 0.52;
+        Vector3::repeat(if open { 0.0 } else { 1.0 })
+    }).collect();
+    let mask = Texture::image(Arc::new(Image::new(WIDTH, HEIGHT, window)), Vector3::repeat(1.0));
+    let walls = preset(library, "white").with_opacity(mask);
 
     let objects = vec![
-        Sphere::new(0, Point3::new(-1005.0, 0.0, -8.0), 1000.0, blue_plastic),
-        Sphere::new(1, Point3::new(1005.0, 0.0, -8.0), 1000.0, red_plastic),
-        Sphere::new(2, Point3::new(0.0, -1003.0, -8.0), 1000.0, white_lambert),
-        Sphere::new(3, Point3::new(0.0, 1003.0, -8.0), 1000.0, white_lambert),
-        Sphere::new(4, Point3::new(0.0, 0.0, -1010.0), 1000.0, white_lambert),
-        Sphere::new(5, Point3::new(0.0, 13.0, -8.0), 10.5, bright_light),
-        Sphere::new(6, Point3::new(1.0, -2.0, -7.0), 1.0, silver),
-        Sphere::new(7, Point3::new(-0.75, -2.0, -5.0), 1.0, glass)
+        Sphere::new(0, Point3::new(0.0, 2.0, -6.0), 6.0, walls),
+        Sphere::new(1, Point3::new(0.0, -1000.0, -6.0), 1000.0, preset(library, "clay")),
+        Sphere::new(2, Point3::new(1.0, 0.8, -7.5), 0.8, preset(library, "red_plastic")),
+        Sphere::new(3, Point3::new(-1.2, 0.6, -8.5), 0.6, preset(library, "gold")),
+        Sphere::new(4, Point3::new(2.5, 0.5, -5.0), 0.5, preset(library, "glass"))
     ];
 
     let camera = Camera::new(
-        Point3::new(0.0, 0.0, 7.0),
+        Point3::new(-1.5, 2.0, -1.0),
         0.024,
-        0.040,
-        15.0,
+        0.024,
+        6.0,
+        2.8,
+        35.0,
+        5.0
+    );
+
+    let mut scene = Scene::new(objects, camera);
+    // a little larger than the hole, which curves back into the room
+    let portal = (Point3::new(6.0, 1.2, -8.6), Vector3::new(0.0, 3.4, 0.0), Vector3::new(0.0, 0.0, 5.2));
+    add_daylight(&mut scene, 0.5, 0.0, 3.0, 0.02, &[portal]);
+    scene
+}
+
+// Spheres under a flat overcast sky, lit by nothing but a gradient from the
+// ground up through a pale horizon
+fn load_overcast_scene(library: &MaterialLibrary) -> Scene {
+    let objects = vec![
+        Sphere::new(0, Point3::new(-2.0, 1.0, -6.0), 1.0, preset(library, "marble")),
+        Sphere::new(1, Point3::new(0.5, 1.0, -5.0), 1.0, preset(library, "blue_plastic")),
+        Sphere::new(2, Point3::new(2.5, 0.7, -4.0), 0.7, preset(library, "gold")),
+        Sphere::new(3, Point3::new(0.0, -1000.0, -8.0), 1000.0, preset(library, "white"))
+    ];
+
+    let camera = Camera::new(
+        Point3::new(0.0, 3.0, 8.0),
+        0.024,
+        0.055,
+        13.0,
         1.4,
         0.0,
-        0.0
+        10.0
     );
 
-    Scene::new(objects, camera)
+    let mut scene = Scene::new(objects, camera);
+    add_background(&mut scene, &Background::three_colour_gradient(
+        Vector3::new(20.0, 18.0, 15.0),
+        Vector3::new(110.0, 110.0, 105.0),
+        Vector3::new(70.0, 76.0, 85.0)
+    ));
+    scene
 }
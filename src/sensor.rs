@@ -17,45 +17,127 @@ impl SensorDimensions {
     }
 }
 
+// Radiance arriving along a camera ray, split by the light group it came
+// from, and how much of the pixel it covers. Anything less than fully opaque
+// lets the plate that the render is composited over show through.
+#[derive(Clone)]
+pub struct Sample {
+    pub groups: Vec<Vector3<f64>>,
+    pub alpha: f64,
+}
+
+impl Sample {
+    pub fn color(&self) -> Vector3<f64> {
+        self.groups.iter().sum()
+    }
+}
+
 #[derive(Clone)]
 struct PixelInfo {
-    color: Vector3<f64>,
+    alpha: f64,
     sensor: u32,
 }
 
 impl PixelInfo {
-    fn color(&self, reciprocal_gamma: f64) -> Vector3<u8> {
-        let color = self.color * (1.0 / f64::from(self.sensor));
+    fn color(&self, color: Vector3<f64>, reciprocal_gamma: f64) -> Vector3<u8> {
+        let color = color * (1.0 / f64::from(self.sensor));
         let color = (color / 255.0)
             .apply_into(|v| v.powf(reciprocal_gamma).min(1.0))
             * 255.0;
         Vector3::new(color.x as u8, color.y as u8, color.z as u8)
     }
+
+    fn alpha(&self) -> u8 {
+        (self.alpha / f64::from(self.sensor) * 255.0).clamp(0.0, 255.0) as u8
+    }
 }
 
+// Accumulates one buffer per light group, which the beauty image is the sum
+// of, so that groups can be rebalanced when compositing.
 pub struct Sensor{
     pixels: Vec<PixelInfo>,
+    groups: usize,
+    colors: Vec<Vector3<f64>>,
     reciprocal_gamma: f64
 }
 
 impl Sensor {
     pub fn new(dimensions: SensorDimensions, reciprocal_gamma: f64) -> Self {
         let default = PixelInfo {
-            color: Vector3::new(0.0, 0.0, 0.0),
+            alpha: 0.0,
             sensor: 0
         };
+        let pixels = dimensions.width * dimensions.height;
         Self{
-            pixels: vec![default; dimensions.width * dimensions.height],
+            pixels: vec![default; pixels],
+            groups: 1,
+            colors: vec![Vector3::zeros(); pixels],
             reciprocal_gamma
         }
     }
 
-    pub fn add_sample(&mut self, position: usize, sample: Vector3<f64>) {
-        self.pixels[position].color += sample;
+    // one buffer for each of the scene's light groups
+    pub fn with_light_groups(self, groups: usize) -> Self {
+        let groups = groups.max(1);
+        Self {
+            colors: vec![Vector3::zeros(); self.pixels.len() * groups],
+            groups,
+            ..self
+        }
+    }
+
+    pub fn reciprocal_gamma(&self) -> f64 {
+        self.reciprocal_gamma
+    }
+
+    pub fn add_sample(&mut self, position: usize, sample: Sample) {
+        assert_eq!(sample.groups.len(), self.groups, "sample has the wrong number of light groups");
+        for (total, light) in self.colors[position * self.groups..].iter_mut().zip(sample.groups) {
+            *total += light;
+        }
+        self.pixels[position].alpha += sample.alpha;
         self.pixels[position].sensor += 1;
     }
 
+    // premultiplied by the alpha
     pub fn color_at(&self, position: usize) -> Vector3<u8> {
-        self.pixels[position].color(self.reciprocal_gamma)
+        let color = self.colors[position * self.groups..(position + 1) * self.groups].iter().sum();
+        self.pixels[position].color(color, self.reciprocal_gamma)
+    }
+
+    // linear radiance in one light group, averaged over the pixel's samples
+    pub fn light_group_at(&self, position: usize, group: usize) -> Vector3<f64> {
+        let pixel = &self.pixels[position];
+        if pixel.sensor == 0 {
+            return Vector3::zeros();
+        }
+        self.colors[position * self.groups + group] / f64::from(pixel.sensor)
     }
-}
\ No newline at end of file
+
+    pub fn alpha_at(&self, position: usize) -> u8 {
+        self.pixels[position].alpha()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn light_groups_add_up_to_the_beauty_image() {
+        let dimensions = SensorDimensions { width: 2, height: 1 };
+        let mut sensor = Sensor::new(dimensions, 1.0).with_light_groups(3);
+        let samples = [
+            vec![Vector3::new(10.0, 0.0, 0.0), Vector3::new(0.0, 20.0, 0.0), Vector3::new(1.0, 2.0, 3.0)],
+            vec![Vector3::new(30.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(5.0, 6.0, 7.0)],
+        ];
+        for groups in samples.iter() {
+            sensor.add_sample(1, Sample { groups: groups.clone(), alpha: 1.0 });
+        }
+
+        let sum = (0..3).map(|group| sensor.light_group_at(1, group)).sum::<Vector3<f64>>();
+        assert_eq!(sensor.light_group_at(1, 0), Vector3::new(20.0, 0.0, 0.0));
+        assert_eq!(sensor.color_at(1), sum.map(|c| c as u8));
+        assert_eq!(sensor.light_group_at(0, 2), Vector3::zeros());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::emission;
+use crate::environment::Environment;
+use crate::image::Image;
+use crate::light::Light;
+use crate::spectrum;
+use nalgebra::Vector3;
+use std::f64;
+use std::sync::Arc;
+
+// angular diameter of the sun seen from the ground
+const SUN_DIAMETER: f64 = 0.0093;
+
+// illuminance of the sun above the atmosphere, in lux
+const SOLAR_ILLUMINANCE: f64 = 128_000.0;
+
+const SUN_TEMPERATURE: f64 = 5778.0;
+
+// wavelengths in micrometres standing in for the red, green and blue primaries
+const PRIMARY_WAVELENGTHS: [f64; 3] = [0.61, 0.55, 0.465];
+
+// Clear sky daylight from the analytic model of Preetham, Shirley and Smits
+// (1999), in nits. Directions below the horizon see nothing, so scenes bring
+// their own ground.
+pub struct Sky {
+    sun: Vector3<f64>,
+    turbidity: f64,
+    // Perez coefficients and zenith values for luminance and chromaticity
+    perez: [[f64; 5]; 3],
+    zenith: [f64; 3],
+}
+
+impl Sky {
+    // The sun's elevation above the horizon and its azimuth, measured from +x
+    // towards +z, are in radians. Turbidity runs from 2 for a very clear sky
+    // to 10 or so for haze.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Self {
+        let t = turbidity;
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let theta = f64::consts::FRAC_PI_2 - elevation.clamp(0.0, f64::consts::FRAC_PI_2);
+        let chi = (4.0 / 9.0 - t / 120.0) * (f64::consts::PI - 2.0 * theta);
+        let luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192) * 1000.0;
+        let cubic = |c: [f64; 4]| c[0] * theta.powi(3) + c[1] * theta.powi(2) + c[2] * theta + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let sun = Vector3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        );
+
+        Self {
+            sun,
+            turbidity,
+            perez,
+            zenith: [luminance.max(0.0), x, y],
+        }
+    }
+
+    pub fn radiance(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        if direction.y <= 0.0 {
+            return Vector3::zeros();
+        }
+
+        let cos_gamma = direction.dot(&self.sun).clamp(-1.0, 1.0);
+        let cos_sun = self.sun.y.clamp(0.0, 1.0);
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * perez(&self.perez[i], direction.y, cos_gamma)
+                / perez(&self.perez[i], 1.0, cos_sun)
+        });
+
+        let xyz = Vector3::new(x * luminance / y, luminance, (1.0 - x - y) * luminance / y);
+        spectrum::xyz_to_rgb(&xyz).map(|c| c.max(0.0))
+    }
+
+    // The sun seen through the atmosphere, reddened by scattering off air
+    // molecules and the aerosols that turbidity counts, with its irradiance
+    // scaled by the intensity.
+    pub fn sun(&self, intensity: f64) -> Light {
+        let zenith_degrees = (90.0 - self.sun.y.clamp(0.0, 1.0).asin().to_degrees()).min(93.0);
+        let air_mass = 1.0 / (zenith_degrees.to_radians().cos() + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = Vector3::from_iterator(PRIMARY_WAVELENGTHS.iter().map(|lambda| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+            rayleigh * aerosol
+        }));
+
+        let irradiance = emission::blackbody(SUN_TEMPERATURE).component_mul(&transmittance) * (SOLAR_ILLUMINANCE * intensity);
+        Light::directional(self.sun, irradiance, SUN_DIAMETER)
+    }
+
+    // the sky baked into an environment map, with its radiance scaled by the
+    // intensity
+    pub fn environment(&self, width: usize, height: usize, intensity: f64) -> Environment {
+        let pixels = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let phi = 2.0 * f64::consts::PI * (x as f64 + 0.5) / width as f64;
+                let theta = f64::consts::PI * (y as f64 + 0.5) / height as f64;
+                self.radiance(&Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()))
+            })
+            .collect();
+        Environment::new(Arc::new(Image::new(width, height, pixels)), 0.0, intensity)
+    }
+}
+
+// Perez distribution over the angle from the zenith and the angle to the sun
+fn perez(coefficients: &[f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    let gamma = cos_gamma.acos();
+    (1.0 + a * (b / cos_theta.max(0.01)).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nalgebra::Point3;
+
+    #[test]
+    fn sky_is_brightest_near_the_sun_and_blue_away_from_it() {
+        let sky = Sky::new(0.5, 1.0, 3.0);
+        let sun = sky.sun;
+        let near_sun = sky.radiance(&(sun + Vector3::new(0.0, 0.05, 0.0)).normalize());
+        let opposite = sky.radiance(&Vector3::new(-sun.x, 0.6, -sun.z).normalize());
+
+        assert!(emission::luminance(&near_sun) > emission::luminance(&opposite));
+        assert!(opposite.z > opposite.x);
+        assert!(emission::luminance(&sky.radiance(&Vector3::new(0.0, 1.0, 0.0))) > 1000.0);
+        assert_eq!(sky.radiance(&Vector3::new(0.0, -0.5, 1.0).normalize()), Vector3::zeros());
+    }
+
+    #[test]
+    fn low_suns_are_dimmer_and_redder() {
+        let high = Sky::new(1.2, 0.0, 3.0).sun(1.0).sample(Point3::origin(), 0.5, 0.5);
+        let low = Sky::new(0.05, 0.0, 3.0).sun(1.0).sample(Point3::origin(), 0.5, 0.5);
+        let (high, low) = (high.radiance, low.radiance);
+        assert!(emission::luminance(&high) > emission::luminance(&low));
+        assert!(low.x / low.z > high.x / high.z);
+    }
+}
@@ -0,0 +1,103 @@
+use nalgebra::Vector3;
+
+pub const MIN_WAVELENGTH: f64 = 380.0;
+pub const MAX_WAVELENGTH: f64 = 780.0;
+
+// wavelength used for dispersive materials on paths that don't carry one
+const REFERENCE_WAVELENGTH: f64 = 550.0;
+
+// makes the average over uniformly sampled wavelengths white
+const WHITE_BALANCE: [f64; 3] = [2.2703708642453404, 3.4666107664479013, 3.659781050339996];
+
+pub fn sample_wavelength(u: f64) -> f64 {
+    MIN_WAVELENGTH + u * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+}
+
+// The rgb weight a path takes on when it is narrowed down to a single
+// uniformly sampled wavelength.
+pub fn wavelength_to_rgb(wavelength: f64) -> Vector3<f64> {
+    xyz_to_rgb(&cie_xyz(wavelength))
+        .map(|c| c.max(0.0))
+        .component_mul(&Vector3::from_column_slice(&WHITE_BALANCE))
+}
+
+// linear sRGB primaries
+pub fn xyz_to_rgb(xyz: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+// multi-lobe fit of the CIE 1931 observer (Wyman et al. 2013)
+pub fn cie_xyz(wavelength: f64) -> Vector3<f64> {
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let sigma = if wavelength < mean { below } else { above };
+        (-0.5 * ((wavelength - mean) / sigma).powi(2)).exp()
+    };
+
+    Vector3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Ior {
+    Constant(f64),
+    // n = a + b / λ², with λ in micrometres
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ bᵢλ² / (λ² - cᵢ), with λ in micrometres
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+
+    pub fn at(&self, wavelength: Option<f64>) -> f64 {
+        let micrometres = wavelength.unwrap_or(REFERENCE_WAVELENGTH) / 1000.0;
+        let l2 = micrometres * micrometres;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => (1.0
+                + b.iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * l2 / (l2 - c))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uniform_wavelengths_average_to_white() {
+        let n = 100_000;
+        let total = (0..n)
+            .map(|i| wavelength_to_rgb(sample_wavelength((i as f64 + 0.5) / n as f64)))
+            .fold(Vector3::zeros(), |acc, c| acc + c);
+        let average = total / n as f64;
+        for c in 0..3 {
+            assert!((average[c] - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn sellmeier_matches_bk7() {
+        let bk7 = Ior::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        };
+        assert!((bk7.at(Some(587.6)) - 1.5168).abs() < 1e-4);
+        assert!(bk7.at(Some(450.0)) > bk7.at(Some(650.0)));
+    }
+}
@@ -1,10 +1,11 @@
 use crate::material::Material;
+use crate::onb::OrthonormalBasis;
 use crate::ray::Ray;
-use nalgebra::Point3;
+use nalgebra::{Point2, Point3, Vector3};
 use std::f64;
 
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Sphere {
     index: usize,
     center: Point3<f64>,
@@ -28,6 +29,10 @@ impl Sphere {
         }
     }
 
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
     // this only really needs to be exposed for bounding sphers
     pub fn radius(&self) -> f64 {
         self.radius
@@ -41,6 +46,115 @@ impl Sphere {
         &self.material
     }
 
+    // latitude/longitude texture coordinates for a point on the surface
+    pub fn uv(&self, normal: &Vector3<f64>) -> Point2<f64> {
+        Point2::new(
+            0.5 + normal.z.atan2(normal.x) / (2.0 * f64::consts::PI),
+            0.5 - normal.y.clamp(-1.0, 1.0).asin() / f64::consts::PI,
+        )
+    }
+
+    // direction of increasing u, around the sphere's vertical axis
+    pub fn tangent(&self, normal: &Vector3<f64>) -> Vector3<f64> {
+        let tangent = Vector3::new(-normal.z, 0.0, normal.x);
+        if tangent.norm_squared() > 1e-12 {
+            tangent.normalize()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        4.0 * f64::consts::PI * self.radius * self.radius
+    }
+
+    // uniformly distributed point on the surface, with the normal there
+    pub fn sample_surface(&self, u: f64, v: f64) -> (Point3<f64>, Vector3<f64>) {
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * f64::consts::PI * v;
+        let normal = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+        (self.center + normal * self.radius, normal)
+    }
+
+    // Picks a point on the part of the sphere visible from a point outside it,
+    // uniformly over the cone of directions it covers, with the point's normal
+    // and the solid angle density of its direction. From inside, every point
+    // is visible and picked uniformly by area.
+    pub fn sample_towards(&self, from: Point3<f64>, u: f64, v: f64) -> (Point3<f64>, Vector3<f64>, f64) {
+        let to_center = self.center - from;
+        let distance_squared = to_center.norm_squared();
+        if distance_squared <= self.radius * self.radius {
+            let (point, normal) = self.sample_surface(u, v);
+            return (point, normal, self.pdf_towards(from, point));
+        }
+
+        let distance = distance_squared.sqrt();
+        let sin2_max = self.radius * self.radius / distance_squared;
+        let cos_max = (1.0 - sin2_max).max(0.0).sqrt();
+        // 1 - cos_max, without cancellation for small and distant lights
+        let cone = sin2_max / (1.0 + cos_max);
+
+        let cos_theta = 1.0 - u * cone;
+        let sin2_theta = (1.0 - cos_theta * cos_theta).max(0.0);
+        let phi = 2.0 * f64::consts::PI * v;
+        let sin_theta = sin2_theta.sqrt();
+        let direction = OrthonormalBasis::from_normal(to_center / distance).local(Vector3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+
+        // nearest intersection along the sampled direction
+        let along = distance * cos_theta
+            - (self.radius * self.radius - distance_squared * sin2_theta).max(0.0).sqrt();
+        let point = from + direction * along;
+        let normal = (point - self.center) / self.radius;
+        (point, normal, 1.0 / (2.0 * f64::consts::PI * cone))
+    }
+
+    // solid angle density of sample_towards picking a point on the surface
+    pub fn pdf_towards(&self, from: Point3<f64>, point: Point3<f64>) -> f64 {
+        let to_center = self.center - from;
+        let distance_squared = to_center.norm_squared();
+        let normal = (point - self.center) / self.radius;
+        let to_point = point - from;
+
+        if distance_squared <= self.radius * self.radius {
+            let cos_light = normal.dot(&to_point).abs() / to_point.norm();
+            return to_point.norm_squared() / (cos_light * self.area());
+        }
+
+        // the far side of the sphere is hidden behind the near side
+        if normal.dot(&to_point) >= 0.0 {
+            return 0.0;
+        }
+
+        let sin2_max = self.radius * self.radius / distance_squared;
+        let cone = sin2_max / (1.0 + (1.0 - sin2_max).max(0.0).sqrt());
+        1.0 / (2.0 * f64::consts::PI * cone)
+    }
+
+    // maps a point into the unit cube bounding the sphere
+    pub fn local(&self, p: Point3<f64>) -> Point3<f64> {
+        let min = self.center - Vector3::repeat(self.radius);
+        Point3::from((p - min) / (2.0 * self.radius))
+    }
+
+    // distances along a ray to where it enters and leaves the sphere, either
+    // of which may be behind its origin
+    pub fn chord(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let op = self.center - ray.origin;
+        let b = op.dot(&ray.direction);
+        let det = b * b - op.dot(&op) + self.radius * self.radius;
+        if det < 0f64 {
+            return None;
+        }
+
+        let det_root = det.sqrt();
+        Some((b - det_root, b + det_root))
+    }
+
     pub fn intersection_distance(&self, ray: &Ray) -> f64 {
         let bias = 1e-6;
         let op = self.center - ray.origin;
@@ -64,3 +178,73 @@ impl Sphere {
         f64::INFINITY
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn light() -> Sphere {
+        let material = Material::emitter(Vector3::new(1.0, 1.0, 1.0));
+        Sphere::new(0, Point3::new(1.0, 2.0, -3.0), 1.5, material)
+    }
+
+    fn samples(n: usize) -> impl Iterator<Item = (f64, f64)> {
+        (0..n).map(move |i| ((i as f64 + 0.5) / n as f64, (i as f64 * 0.618_033_988_75).fract()))
+    }
+
+    #[test]
+    fn cone_samples_land_on_the_visible_side() {
+        let sphere = light();
+        let from = Point3::new(-2.0, 0.5, 1.0);
+        for (u, v) in samples(10_000) {
+            let (point, normal, pdf) = sphere.sample_towards(from, u, v);
+            assert!(((point - sphere.center()).norm() - sphere.radius()).abs() < 1e-9);
+            assert!(normal.dot(&(from - point)) >= -1e-9);
+            assert!((pdf - sphere.pdf_towards(from, point)).abs() < 1e-9 * pdf);
+        }
+    }
+
+    #[test]
+    fn cone_sampling_integrates_projected_solid_angle() {
+        // ∫ cos θ dω over a cone of half angle θmax is π sin²θmax
+        let sphere = light();
+        let from = Point3::new(-2.0, 0.5, 1.0);
+        let axis = (sphere.center() - from).normalize();
+        let sin2_max = sphere.radius().powi(2) / (sphere.center() - from).norm_squared();
+
+        let n = 100_000;
+        let estimate = samples(n)
+            .map(|(u, v)| {
+                let (point, _, pdf) = sphere.sample_towards(from, u, v);
+                (point - from).normalize().dot(&axis) / pdf
+            })
+            .sum::<f64>()
+            / n as f64;
+        assert!((estimate / (f64::consts::PI * sin2_max) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn density_integrates_to_one_over_uniform_directions() {
+        let sphere = light();
+        for from in [Point3::new(-2.0, 0.5, 1.0), Point3::new(1.5, 2.5, -3.0)].iter() {
+            let n = 400_000;
+            let total = samples(n)
+                .map(|(u, v)| {
+                    let z = 1.0 - 2.0 * u;
+                    let r = (1.0 - z * z).sqrt();
+                    let phi = 2.0 * f64::consts::PI * v;
+                    let direction = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+                    let ray = Ray { origin: *from, direction };
+                    let distance = sphere.intersection_distance(&ray);
+                    if distance.is_finite() {
+                        sphere.pdf_towards(*from, *from + direction * distance)
+                    } else {
+                        0.0
+                    }
+                })
+                .sum::<f64>();
+            let integral = total * 4.0 * f64::consts::PI / n as f64;
+            assert!((integral - 1.0).abs() < 0.01, "integral {}", integral);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::image::Image;
+use nalgebra::{Point2, Vector3};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub enum Texture {
+    Constant(Vector3<f64>),
+    Image { image: Arc<Image>, scale: Vector3<f64> },
+}
+
+impl Texture {
+    pub fn scalar(value: f64) -> Self {
+        Texture::Constant(Vector3::repeat(value))
+    }
+
+    pub fn image(image: Arc<Image>, scale: Vector3<f64>) -> Self {
+        Texture::Image { image, scale }
+    }
+
+    pub fn value(&self, uv: Point2<f64>) -> Vector3<f64> {
+        match self {
+            Texture::Constant(value) => *value,
+            Texture::Image { image, scale } => image.lookup(uv).component_mul(scale),
+        }
+    }
+
+    // single channel lookups, e.g. thickness or opacity maps
+    pub fn value_mean(&self, uv: Point2<f64>) -> f64 {
+        self.value(uv).mean()
+    }
+}
@@ -1,8 +1,13 @@
 use crate::ray::{Ray};
-use crate::scene::{Scene};
-use crate::sensor::{SensorDimensions, Sensor};
-use crate::material::{SurfaceInteraction, SurfacePoint};
+use crate::scene::{Scene, Intersection};
+use crate::sensor::{SensorDimensions, Sensor, Sample};
+use crate::material::{Material, Matte, SurfaceInteraction, SurfacePoint};
+use crate::medium::{HenyeyGreenstein, MediumSample};
+use crate::sphere::Sphere;
+use crate::spectrum;
+use crate::emission;
 pub use nalgebra::Vector3;
+use nalgebra::Point3;
 
 pub trait Screen {
     fn write(&mut self, i: usize, r: u8, g: u8, b: u8);
@@ -27,6 +32,12 @@ impl SingleThreadedTracer {
     }
 
     pub fn update(&mut self, scene: &Scene, screen: &mut impl Screen) {
+        // the scene decides how many light groups the sensor keeps
+        if self.sample_count == 0 {
+            self.sensor = Sensor::new(self.dimensions, self.sensor.reciprocal_gamma())
+                .with_light_groups(scene.light_groups().len());
+        }
+
         let pixel = self.dimensions.pixel_for_index(self.sample_count);
         
         let ray = scene.camera.ray(
@@ -66,20 +77,33 @@ impl<'a> StratisfiedImageSampler<'a> {
 }
 
 impl<'a> Iterator for StratisfiedImageSampler<'a> {
-    type Item=Vector3<f64>;
+    type Item=Sample;
 
-    fn next(&mut self) -> Option<Vector3<f64>> {
-        let mut total_energy = Vector3::new(0.0, 0.0, 0.0);
+    fn next(&mut self) -> Option<Sample> {
+        let mut total_energy = vec![Vector3::new(0.0, 0.0, 0.0); self.scene.light_groups().len()];
+        let mut total_alpha = 0.0;
         let n = f64::from(self.samples).sqrt() as u32;
         for u in 0..n {
             for v in 0..n {
                 let fu = (f64::from(u) + rand::random::<f64>()) / f64::from(n);
                 let fv = (f64::from(v) + rand::random::<f64>()) / f64::from(n);
-                total_energy += LightPath::new(&self.scene, self.ray, (fu, fv)).take(self.bounces).sum::<Vector3<f64>>();
+                let mut path = LightPath::new(&self.scene, self.ray, (fu, fv));
+                for _ in 0..self.bounces {
+                    if !path.step() {
+                        break;
+                    }
+                }
+                for (total, light) in total_energy.iter_mut().zip(path.groups.iter()) {
+                    *total += light;
+                }
+                total_alpha += path.alpha;
             }
         }
 
-        Some(total_energy / f64::from(n*n))
+        Some(Sample {
+            groups: total_energy.iter().map(|energy| energy / f64::from(n*n)).collect(),
+            alpha: total_alpha / f64::from(n*n)
+        })
     }
 }
 
@@ -87,51 +111,452 @@ struct LightPath<'a> {
     scene: &'a Scene,
     ray: Ray,
     signal: Vector3<f64>,
-    uv: (f64, f64)
+    uv: (f64, f64),
+    wavelength: Option<f64>,
+    interiors: Interiors<'a>,
+    primary: bool,
+    alpha: f64,
+    // where the last bounce that light sampling also covered happened, and
+    // the density its direction was sampled with
+    vertex: Option<(Point3<f64>, f64)>,
+    // Where a camera ray was reflected off a shadow catcher, in which
+    // direction and with what signal. The photograph already shows the light
+    // from there, so it's taken out once the path meets the scene.
+    plate: Option<(Point3<f64>, Vector3<f64>, Vector3<f64>)>,
+    // light gathered so far in each of the scene's light groups
+    groups: Vec<Vector3<f64>>
+}
+
+// scattering events in a medium after which the walk is continued by russian
+// roulette on its throughput
+const ROULETTE_SCATTERING_EVENTS: usize = 32;
+
+// lights sampled to find how much of a shadow catcher is in shadow
+const SHADOW_SAMPLES: usize = 4;
+
+// The transmissive objects a path is inside, in the order it entered them.
+// Where they overlap the highest priority one decides the medium and index,
+// and the surfaces of the others are ignored until the path leaves it.
+struct Interiors<'a> {
+    objects: Vec<&'a Sphere>
+}
+
+impl<'a> Interiors<'a> {
+    fn new() -> Self {
+        Self{objects: Vec::new()}
+    }
+
+    // ties go to the most recently entered object
+    fn innermost(&self, excluding: Option<&Sphere>) -> Option<&'a Sphere> {
+        self.objects
+            .iter()
+            .filter(|object| Some(**object) != excluding)
+            .max_by_key(|object| object.material().priority())
+            .cloned()
+    }
+
+    // index on the far side of an object's surface from its interior
+    fn exterior_index(&self, object: &Sphere, wavelength: Option<f64>) -> f64 {
+        self.innermost(Some(object))
+            .map_or(1.0, |outer| outer.material().interior_index(wavelength))
+    }
+
+    fn is_false_intersection(&self, object: &Sphere) -> bool {
+        object.material().is_enclosure() && self.innermost(Some(object)).is_some_and(|outer| {
+            outer.material().priority() > object.material().priority()
+        })
+    }
+
+    fn cross(&mut self, object: &'a Sphere, entering: bool) {
+        if entering {
+            self.objects.push(object);
+        } else if let Some(i) = self.objects.iter().rposition(|o| *o == object) {
+            self.objects.remove(i);
+        }
+    }
+}
+
+// weight for combining two sampling strategies (Veach 1997)
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
 }
 
 impl<'a> LightPath<'a> {
     fn new(scene: &'a Scene, ray: Ray, first_uv: (f64, f64)) -> Self {
-        Self{scene, ray, signal: Vector3::new(1.0, 1.0, 1.0), uv: first_uv}
+        Self{
+            scene,
+            ray,
+            signal: Vector3::new(1.0, 1.0, 1.0),
+            uv: first_uv,
+            wavelength: None,
+            interiors: Interiors::new(),
+            primary: true,
+            alpha: 1.0,
+            vertex: None,
+            plate: None,
+            groups: vec![Vector3::zeros(); scene.light_groups().len()]
+        }
+    }
+
+    // adds light reaching the camera to its group
+    fn gather(&mut self, group: usize, light: Vector3<f64>) {
+        self.groups[group] += light;
     }
-}
 
-impl<'a> Iterator for LightPath<'a> {
-    type Item=Vector3<f64>;
+    // Light from a sampled point on a light that reaches a surface, weighted
+    // against the chance of the bsdf having sampled the same direction, with
+    // the light's group.
+    fn direct_light(&self, material: &Material, interaction: &SurfaceInteraction) -> Option<(Vector3<f64>, usize)> {
+        self.sampled_light(interaction.surface.p, false, |direction| material.eval(interaction, direction))
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.signal == Vector3::zeros() {
+    // as direct_light, for a point scattering inside a medium, weighted
+    // against the phase function
+    fn in_scattered_light(
+        &self,
+        phase: &HenyeyGreenstein,
+        p: Point3<f64>,
+        incoming: &Vector3<f64>
+    ) -> Option<(Vector3<f64>, usize)> {
+        self.sampled_light(p, true, |direction| {
+            let pdf = phase.pdf(incoming, &direction);
+            (Vector3::repeat(pdf), pdf)
+        })
+    }
+
+    // Light from a sampled light scattered at a point, given the fraction
+    // scattered towards the path from a direction and the density of the
+    // path having been scattered that way.
+    fn sampled_light(
+        &self,
+        p: Point3<f64>,
+        in_medium: bool,
+        scatter: impl Fn(Vector3<f64>) -> (Vector3<f64>, f64)
+    ) -> Option<(Vector3<f64>, usize)> {
+        let (sample, group) = self.scene.sample_light(p, rand::random(), rand::random(), rand::random())?;
+        if sample.radiance == Vector3::zeros() {
             return None;
         }
 
-        if let Some(intersect) = self.scene.intersect(&self.ray) {
+        let (scattered, scattering_pdf) = scatter(sample.direction);
+        if scattered == Vector3::zeros() {
+            return None;
+        }
+
+        let visibility = match sample.pdf {
+            None if in_medium => self.scene.delta_transmittance_towards(p, sample.direction, sample.distance),
+            _ => self.scene.transmittance_towards(p, sample.direction, sample.distance)
+        };
+        if visibility == Vector3::zeros() {
+            return None;
+        }
+
+        let light = sample.radiance.component_mul(&scattered).component_mul(&visibility);
+        match sample.pdf {
+            Some(light_pdf) => Some((light * (power_heuristic(light_pdf, scattering_pdf) / light_pdf), group)),
+            None => Some((light, group))
+        }
+    }
+
+    // How much of the light reaching a shadow catcher the rest of the scene
+    // blocks, from the light sampled there with and without anything in the
+    // way. The plate already holds the light that gets through.
+    fn shadow(&self, material: &Material, interaction: &SurfaceInteraction) -> f64 {
+        let (mut unblocked, mut reaching) = (0.0, 0.0);
+        for _ in 0..SHADOW_SAMPLES {
+            let p = interaction.surface.p;
+            let sample = match self.scene.sample_light(p, rand::random(), rand::random(), rand::random()) {
+                Some((sample, _)) => sample,
+                None => continue
+            };
+            let (scattered, _) = material.eval(interaction, sample.direction);
+            let light = sample.radiance.component_mul(&scattered);
+            let light = match sample.pdf {
+                Some(pdf) if pdf > 0.0 => light / pdf,
+                Some(_) => continue,
+                None => light
+            };
+            let visibility = self.scene.transmittance_towards(p, sample.direction, sample.distance);
+            unblocked += emission::luminance(&light);
+            reaching += emission::luminance(&light.component_mul(&visibility));
+        }
+
+        if unblocked > 0.0 { 1.0 - reaching / unblocked } else { 0.0 }
+    }
+
+    // Follows a camera ray off a shadow catcher's specular reflection. What
+    // its diffuse side scatters is already in the plate.
+    fn reflect_off_catcher(&mut self, material: &Material, interaction: &SurfaceInteraction) -> bool {
+        let sample = material.bsdf(interaction, self.uv.0, self.uv.1);
+        self.uv = (rand::random(), rand::random());
+        if sample.pdf.is_some() || sample.signal == Vector3::zeros() {
+            self.signal = Vector3::zeros();
+            return false;
+        }
+
+        self.signal = self.signal.component_mul(&sample.signal);
+        self.ray = Ray{origin: interaction.surface.p, direction: sample.direction};
+        self.plate = Some((interaction.surface.p, sample.direction, self.signal));
+        self.vertex = None;
+        true
+    }
+
+    // takes out the light the plate shows reflected in a shadow catcher
+    fn subtract_plate(&mut self) {
+        if let Some((from, direction, signal)) = self.plate.take() {
+            for (radiance, _, group) in self.scene.escaped_light(from, &direction) {
+                self.gather(group, -radiance.component_mul(&signal));
+            }
+        }
+    }
+
+    // share of emission found by bsdf sampling, against light sampling
+    fn emission_weight(&self, intersect: &Intersection) -> f64 {
+        match self.vertex {
+            Some((vertex, bsdf_pdf)) => {
+                let light_pdf = self.scene.emitter_pdf(vertex, intersect.object, intersect.hit);
+                power_heuristic(bsdf_pdf, light_pdf)
+            }
+            None => 1.0
+        }
+    }
+
+    // Follows the ray through any medium it's travelling in up to the next
+    // surface. Scattering inside a medium doesn't count as a bounce, so random
+    // walks through dense media aren't cut short, and long walks are ended by
+    // russian roulette, which keeps their light on average. Lights are
+    // sampled at every point the path scatters at.
+    fn next_surface(&mut self) -> Option<Intersection<'a>> {
+        let mut scattering_events = 0;
+        loop {
+            let intersect = self.scene.intersect(&self.ray)?;
+
+            let enclosing = self.interiors.innermost(None);
+            if let Some((object, medium)) = enclosing.and_then(|o| o.material().medium().map(|m| (o, m))) {
+                match medium.sample(&self.ray, intersect.distance, object) {
+                    MediumSample::Scattered { distance, weight } => {
+                        let point = self.ray.origin + self.ray.direction * distance;
+                        let incoming = self.ray.direction;
+                        self.signal = self.signal.component_mul(&weight);
+                        scattering_events += 1;
+                        self.primary = false;
+                        self.subtract_plate();
+
+                        let phase = medium.phase();
+                        if let Some((light, group)) = self.in_scattered_light(phase, point, &incoming) {
+                            self.gather(group, light.component_mul(&self.signal));
+                        }
+                        let direction = phase.sample(&incoming, rand::random(), rand::random());
+                        self.vertex = Some((point, phase.pdf(&incoming, &direction)));
+                        self.ray = Ray { origin: point, direction };
+                        if scattering_events > ROULETTE_SCATTERING_EVENTS {
+                            let survival = self.signal.max().min(1.0);
+                            if rand::random::<f64>() >= survival {
+                                self.signal = Vector3::zeros();
+                                return Some(intersect);
+                            }
+                            self.signal /= survival;
+                        }
+                        continue;
+                    }
+                    MediumSample::Transmitted { weight } => {
+                        self.signal = self.signal.component_mul(&weight);
+                    }
+                    MediumSample::Absorbed => {
+                        self.subtract_plate();
+                        self.signal = Vector3::zeros();
+                        return Some(intersect);
+                    }
+                }
+            }
+
+            // cutouts let some of the paths through untouched
+            let opacity = intersect.material.opacity(intersect.uv);
+            if opacity < 1.0 && rand::random::<f64>() >= opacity {
+                self.ray = Ray{origin: intersect.hit, direction: self.ray.direction};
+                continue;
+            }
+
+            // surfaces inside a higher priority object don't exist for the path
+            if self.interiors.is_false_intersection(intersect.object) {
+                let entering = self.ray.direction.dot(&intersect.normal) < 0.0;
+                self.interiors.cross(intersect.object, entering);
+                self.ray = Ray{origin: intersect.hit, direction: self.ray.direction};
+                continue;
+            }
+
+            return Some(intersect);
+        }
+    }
+
+    // Follows the path to its next surface, gathering the light found on
+    // the way, and returns whether the path goes on from there.
+    fn step(&mut self) -> bool {
+        if let Some(intersect) = self.next_surface() {
+            // surfaces the path passes straight through, such as the
+            // boundary of smoke, leave it a camera ray
+            if self.primary && !intersect.material.is_index_matched() {
+                self.primary = false;
+                match intersect.material.matte() {
+                    Some(Matte::Holdout) => {
+                        self.alpha = 0.0;
+                        self.signal = Vector3::zeros();
+                        return false;
+                    }
+                    Some(Matte::ShadowCatcher) => {
+                        let seen_from_behind = self.ray.direction.dot(&intersect.normal) > 0.0;
+                        let interaction = SurfaceInteraction{
+                            wo: -self.ray.direction,
+                            surface: SurfacePoint{
+                                p: intersect.hit,
+                                n: if seen_from_behind { -intersect.normal } else { intersect.normal },
+                                uv: intersect.uv,
+                                tangent: intersect.tangent
+                            },
+                            wavelength: None,
+                            exterior_index: 1.0
+                        };
+                        self.alpha = self.shadow(intersect.material, &interaction);
+                        return self.reflect_off_catcher(intersect.material, &interaction);
+                    }
+                    None => {}
+                }
+            } else if self.plate.is_some() && !intersect.material.is_index_matched() {
+                // the photograph's own objects show in the reflection as they are
+                if intersect.material.matte().is_some() {
+                    self.signal = Vector3::zeros();
+                    return false;
+                }
+                self.subtract_plate();
+            }
+
+            // the first dispersive surface narrows the path to one wavelength
+            if self.wavelength.is_none() && intersect.material.is_dispersive() {
+                let wavelength = spectrum::sample_wavelength(rand::random());
+                self.signal = self.signal.component_mul(&spectrum::wavelength_to_rgb(wavelength));
+                self.wavelength = Some(wavelength);
+            }
+
+            // only objects that can be entered have an inside, other surfaces
+            // are seen from the front whichever side the path arrives from
+            let seen_from_behind = self.ray.direction.dot(&intersect.normal) > 0.0;
+            let normal = if seen_from_behind && !intersect.material.is_enclosure() {
+                -intersect.normal
+            } else {
+                intersect.normal
+            };
+
             let interaction = SurfaceInteraction{
                 wo: -self.ray.direction,
                 surface: SurfacePoint{
                     p: intersect.hit,
-                    n: intersect.normal
-                }
+                    n: normal,
+                    uv: intersect.uv,
+                    tangent: intersect.tangent
+                },
+                wavelength: self.wavelength,
+                exterior_index: self.interiors.exterior_index(intersect.object, self.wavelength)
             };
 
-            let sample = intersect
-                .material
-                .bsdf(
-                    &self.scene,
-                    interaction,
-                    intersect.distance,
-                    self.uv.0,
-                    self.uv.1,
-                );
+            let facing = interaction.wo.dot(&intersect.normal);
+            let emitted = intersect.material.emit(intersect.uv, facing);
+            if emitted != Vector3::zeros() {
+                let light = emitted.component_mul(&self.signal) * self.emission_weight(&intersect);
+                self.gather(self.scene.emitter_group(intersect.object), light);
+            }
+
+            let sample = intersect.material.bsdf(&interaction, self.uv.0, self.uv.1);
             self.uv = (rand::random(), rand::random());
 
-            let contribution = intersect.material.emit().component_mul(&self.signal);
+            self.vertex = match sample.pdf {
+                Some(pdf) => {
+                    if let Some((light, group)) = self.direct_light(intersect.material, &interaction) {
+                        self.gather(group, light.component_mul(&self.signal));
+                    }
+                    Some((intersect.hit, pdf))
+                }
+                // passing straight through a surface, e.g. an emitter or the
+                // boundary of smoke, which light sampling sees through too
+                None if intersect.material.is_index_matched() => self.vertex,
+                _ => None
+            };
+
+            let leaving = sample.direction.dot(&intersect.normal);
+            if facing * leaving < 0.0 && intersect.material.is_enclosure() {
+                self.interiors.cross(intersect.object, leaving < 0.0);
+            }
+
             self.ray = Ray{origin: intersect.hit, direction: sample.direction};
             self.signal = self.signal.component_mul(&sample.signal);
-            Some(contribution)
+            self.signal != Vector3::zeros()
         } else {
-            let contribution = self.scene.bg(&self.ray).component_mul(&self.signal);
+            // the camera's own background goes in with the lights that have
+            // no group
+            if self.primary {
+                if let Some(background) = self.scene.camera_background(&self.ray.direction) {
+                    let light = background.component_mul(&self.signal);
+                    self.gather(0, light);
+                    self.signal = Vector3::zeros();
+                    return false;
+                }
+            }
+
+            // the ray may have passed through cutouts since it left the
+            // vertex, which is where light sampling would have started
+            let from = self.vertex.map_or(self.ray.origin, |(vertex, _)| vertex);
+            for (radiance, light_pdf, group) in self.scene.escaped_light(from, &self.ray.direction) {
+                let weight = match self.vertex {
+                    Some((_, bsdf_pdf)) => power_heuristic(bsdf_pdf, light_pdf),
+                    None => 1.0
+                };
+                self.gather(group, radiance.component_mul(&self.signal) * weight);
+            }
+            self.subtract_plate();
             self.signal = Vector3::zeros();
-            Some(contribution)
+            false
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::light::Light;
+    use crate::medium::Medium;
+
+    #[test]
+    fn glossy_shadow_catchers_show_what_they_reflect() {
+        // a lamp above the floor, seen reflected in it from the camera
+        let camera = || Camera::new(Point3::new(0.0, 1.0, 0.0), 0.024, 0.040, 5.0, 1.4, 0.0, 0.0);
+        let catcher = |reflectance| Scene::new(vec![
+            Sphere::new(0, Point3::new(0.0, -1000.0, 0.0), 1000.0, Material::shadow_catcher(reflectance, 1.0)),
+            Sphere::new(1, Point3::new(0.0, 1.0, -4.0), 0.5, Material::emitter(Vector3::repeat(100.0))),
+        ], camera());
+        let ray = Ray{origin: Point3::new(0.0, 1.0, 0.0), direction: Vector3::new(0.0, -1.0, -2.0).normalize()};
+        let seen = |scene: &Scene| (0..100)
+            .map(|_| StratisfiedImageSampler::new(scene, ray, 1, 4).next().unwrap().color().x)
+            .sum::<f64>();
+
+        assert!(seen(&catcher(0.9)) > 0.0);
+        assert_eq!(seen(&catcher(0.0)), 0.0);
+    }
+
+    #[test]
+    fn point_lights_reach_into_media() {
+        let lit = |material: Material| {
+            let camera = Camera::new(Point3::new(0.0, 0.0, 5.0), 0.024, 0.040, 5.0, 1.4, 0.0, 0.0);
+            let mut scene = Scene::new(vec![Sphere::new(0, Point3::origin(), 1.0, material)], camera);
+            scene.add_light(Light::point(Point3::new(0.0, 4.0, 0.0), Vector3::repeat(100.0)));
+            let ray = Ray{origin: Point3::new(0.0, 0.0, 5.0), direction: Vector3::new(0.0, 0.0, -1.0)};
+            (0..100)
+                .map(|_| StratisfiedImageSampler::new(&scene, ray, 1, 8).next().unwrap().color().x)
+                .sum::<f64>()
+        };
+
+        let smoke = Medium::new(Vector3::repeat(0.1), Vector3::repeat(1.0));
+        assert!(lit(Material::volume(smoke)) > 0.0);
+        assert!(lit(Material::subsurface(Vector3::repeat(0.8), Vector3::repeat(0.1), 1.4)) > 0.0);
+    }
+}
@@ -0,0 +1,130 @@
+use nalgebra::Point3;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+// A density grid sampled at voxel centres across the unit cube.
+//
+// Files start with an ascii header line `VOL <nx> <ny> <nz>` followed by
+// nx * ny * nz little endian f32 densities, x varying fastest.
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<f32>,
+    max_density: f64,
+}
+
+impl VoxelGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<f32>) -> Self {
+        assert_eq!(data.len(), nx * ny * nz);
+        let max_density = data.iter().fold(0.0f64, |max, d| max.max(f64::from(*d)));
+        Self {
+            nx,
+            ny,
+            nz,
+            data,
+            max_density,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let header_end = bytes
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(|| invalid("missing volume header"))?;
+        let header = std::str::from_utf8(&bytes[..header_end])
+            .map_err(|_| invalid("volume header is not ascii"))?;
+
+        let mut fields = header.split_whitespace();
+        if fields.next() != Some("VOL") {
+            return Err(invalid("not a volume file"));
+        }
+
+        let mut dimension = || {
+            fields
+                .next()
+                .and_then(|f| f.parse::<usize>().ok())
+                .filter(|d| *d > 0)
+                .ok_or_else(|| invalid("bad volume dimensions"))
+        };
+        let (nx, ny, nz) = (dimension()?, dimension()?, dimension()?);
+
+        let body = &bytes[header_end + 1..];
+        if body.len() != nx * ny * nz * 4 {
+            return Err(invalid("volume data does not match its dimensions"));
+        }
+
+        let data = body
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]).max(0.0))
+            .collect();
+
+        Ok(Self::new(nx, ny, nz, data))
+    }
+
+    pub fn max_density(&self) -> f64 {
+        self.max_density
+    }
+
+    // trilinearly interpolated density at a point in the unit cube
+    pub fn density(&self, p: Point3<f64>) -> f64 {
+        if p.iter().any(|c| *c < 0.0 || *c > 1.0) {
+            return 0.0;
+        }
+
+        let x = p.x * self.nx as f64 - 0.5;
+        let y = p.y * self.ny as f64 - 0.5;
+        let z = p.z * self.nz as f64 - 0.5;
+        let (fx, fy, fz) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (x, y, z) = (x.floor() as isize, y.floor() as isize, z.floor() as isize);
+
+        let mut total = 0.0;
+        for (dz, wz) in [(0, 1.0 - fz), (1, fz)].iter() {
+            for (dy, wy) in [(0, 1.0 - fy), (1, fy)].iter() {
+                for (dx, wx) in [(0, 1.0 - fx), (1, fx)].iter() {
+                    total += wx * wy * wz * self.voxel(x + dx, y + dy, z + dz);
+                }
+            }
+        }
+
+        total
+    }
+
+    fn voxel(&self, x: isize, y: isize, z: isize) -> f64 {
+        let clamp = |v: isize, n: usize| v.clamp(0, n as isize - 1) as usize;
+        let (x, y, z) = (clamp(x, self.nx), clamp(y, self.ny), clamp(z, self.nz));
+        f64::from(self.data[x + self.nx * (y + self.ny * z)])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_header_and_densities() {
+        let mut bytes = b"VOL 2 1 1\n".to_vec();
+        bytes.extend_from_slice(&0.5f32.to_le_bytes());
+        bytes.extend_from_slice(&1.5f32.to_le_bytes());
+
+        let grid = VoxelGrid::parse(&bytes).unwrap();
+        assert_eq!(grid.max_density(), 1.5);
+        assert_eq!(grid.density(Point3::new(0.25, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.density(Point3::new(0.5, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.density(Point3::new(1.5, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let mut bytes = b"VOL 2 2 2\n".to_vec();
+        bytes.extend_from_slice(&0.5f32.to_le_bytes());
+        assert!(VoxelGrid::parse(&bytes).is_err());
+    }
+}